@@ -0,0 +1,263 @@
+//! Sprite animations played from Aseprite files.
+use hecs::{Entity, EntityBuilder, World};
+use macroquad::prelude::*;
+
+use crate::{
+    aseprite::{AsepriteFile, Tag, TagDirection},
+    asset_server::AssetServer,
+    components::{Health, Sprite, Tint, Transform},
+    damage::Invulnerability,
+    physic::{PhysicsResources, RigidBodyHandleComponent},
+};
+
+/// Speed under which an entity is considered standing still.
+const WALK_SPEED_THRESHOLD: f32 = 1.0;
+/// Fallback for frames without duration, so an animation can't loop forever in a single update.
+const DEFAULT_FRAME_DURATION: f32 = 0.1;
+
+/// Frames of an Aseprite file, packed in a single texture.
+pub struct SpriteSheet {
+    /// Area of each frame in the texture.
+    pub frames: Vec<Rect>,
+    /// Duration of each frame, in seconds.
+    pub durations: Vec<f32>,
+    pub tags: Vec<Tag>,
+}
+
+impl SpriteSheet {
+    /// Pack the frames of `file` in a grid, as square as possible.
+    pub fn pack(file: &AsepriteFile) -> (Image, SpriteSheet) {
+        let count = file.frames.len().max(1) as u32;
+        let columns = (count as f32).sqrt().ceil() as u32;
+        let rows = count.div_ceil(columns);
+        let (width, height) = (file.width, file.height);
+
+        let mut image = Image::gen_image_color(
+            (width * columns) as u16,
+            (height * rows) as u16,
+            Color::new(0.0, 0.0, 0.0, 0.0),
+        );
+        let mut frames = Vec::with_capacity(file.frames.len());
+        for (index, pixels) in file.frames.iter().enumerate() {
+            let (x, y) = (
+                index as u32 % columns * width,
+                index as u32 / columns * height,
+            );
+            for row in 0..height {
+                let src = (row * width * 4) as usize;
+                let dst = (((y + row) * width * columns + x) * 4) as usize;
+                image.bytes[dst..dst + (width * 4) as usize]
+                    .copy_from_slice(&pixels[src..src + (width * 4) as usize]);
+            }
+            frames.push(Rect::new(x as f32, y as f32, width as f32, height as f32));
+        }
+
+        let sheet = SpriteSheet {
+            frames,
+            durations: file.durations.clone(),
+            tags: file.tags.clone(),
+        };
+        (image, sheet)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// Frame shown at `step` of the tag, or of the whole file without tag.
+    /// Returns `None` past the last step.
+    fn frame_at(&self, tag: Option<&Tag>, step: usize) -> Option<usize> {
+        let (from, to, direction) = match tag {
+            Some(tag) => (tag.from, tag.to, tag.direction),
+            None => (
+                0,
+                self.frames.len().saturating_sub(1),
+                TagDirection::Forward,
+            ),
+        };
+        let count = to.saturating_sub(from) + 1;
+        // Ping-pong doesn't repeat the frames at both ends
+        let length = match direction {
+            TagDirection::Forward | TagDirection::Reverse => count,
+            TagDirection::PingPong | TagDirection::PingPongReverse => (count * 2 - 2).max(1),
+        };
+        if step >= length {
+            return None;
+        }
+
+        let offset = if step < count { step } else { length - step };
+        let frame = match direction {
+            TagDirection::Forward | TagDirection::PingPong => from + offset,
+            TagDirection::Reverse | TagDirection::PingPongReverse => to - offset,
+        };
+        Some(frame.min(self.frames.len().saturating_sub(1)))
+    }
+}
+
+/// Play the tags of the `Sprite` sheet.
+pub struct Animator {
+    /// Tag being played. Without it, or if the file doesn't have it, every frame is played.
+    pub tag: Option<String>,
+    pub looping: bool,
+    step: usize,
+    elapsed: f32,
+    finished: bool,
+}
+
+impl Animator {
+    pub fn new(tag: Option<&str>) -> Self {
+        Self {
+            tag: tag.map(str::to_owned),
+            looping: true,
+            step: 0,
+            elapsed: 0.0,
+            finished: false,
+        }
+    }
+
+    /// Loop `tag`, from its start unless it is already playing.
+    pub fn play(&mut self, tag: &str) {
+        if self.tag.as_deref() != Some(tag) || !self.looping {
+            self.tag = Some(tag.to_owned());
+            self.looping = true;
+            self.restart();
+        }
+    }
+
+    /// Play `tag` once and hold its last frame.
+    pub fn play_once(&mut self, tag: &str) {
+        self.tag = Some(tag.to_owned());
+        self.looping = false;
+        self.restart();
+    }
+
+    fn restart(&mut self) {
+        self.step = 0;
+        self.elapsed = 0.0;
+        self.finished = false;
+    }
+
+    /// Returns `true` once an animation played with `play_once` reached its end.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Killed enemies playing their `death` tag, drawn under the living.
+///
+/// They live in a world of their own: they leave when the animation ends, on a frame rather
+/// than a tick, and despawning them from the run world would shift the ids of the simulation.
+/// Only runs with a window have it, headless runs have nothing to draw.
+#[derive(Default)]
+pub struct Corpses(pub World);
+
+impl Corpses {
+    /// Play the `death` tag of `sprite` once, where the killed entity was.
+    pub fn spawn(&mut self, transform: &Transform, sprite: &Sprite, tint: Option<&Tint>) {
+        let mut animator = Animator::new(None);
+        animator.play_once("death");
+
+        let mut corpse = EntityBuilder::new();
+        corpse.add_bundle((
+            Transform {
+                position: transform.position,
+                scale: transform.scale,
+                rotation: transform.rotation,
+            },
+            Sprite {
+                asset_id: sprite.asset_id,
+                scale: sprite.scale,
+                source: sprite.source,
+            },
+            animator,
+        ));
+        if let Some(tint) = tint {
+            corpse.add(Tint(tint.0));
+        }
+        self.0.spawn(corpse.build());
+    }
+}
+
+/// Despawn the corpses done with their `death` animation.
+/// Sprites without a sheet have nothing to play, they go right away.
+pub fn corpse_system(corpses: &mut Corpses, asset_server: &AssetServer) {
+    let done: Vec<Entity> = corpses
+        .0
+        .query::<(&Sprite, &Animator)>()
+        .iter()
+        .filter(|(_id, (sprite, animator))| {
+            animator.is_finished() || asset_server.get_sprite_sheet(sprite.asset_id).is_none()
+        })
+        .map(|(id, _)| id)
+        .collect();
+    for id in done {
+        let _ = corpses.0.despawn(id);
+    }
+}
+
+/// Pick the tag of animated entities from what they are doing: hit, walk or idle.
+pub fn animation_state_system(world: &mut World, physics: &PhysicsResources) {
+    for (_id, (animator, body_handle, invulnerability)) in world
+        .query_mut::<(
+            &mut Animator,
+            &RigidBodyHandleComponent,
+            Option<&Invulnerability>,
+        )>()
+        .with::<&Health>()
+    {
+        let moving = physics
+            .rigid_body_set
+            .get(body_handle.0)
+            .is_some_and(|body| body.linvel().norm() > WALK_SPEED_THRESHOLD);
+
+        if invulnerability.is_some_and(Invulnerability::is_active) {
+            animator.play("hit");
+        } else if moving {
+            animator.play("walk");
+        } else {
+            animator.play("idle");
+        }
+    }
+}
+
+/// Advance animations by `dt` seconds, and point each sprite at its current frame.
+pub fn animation_system(world: &mut World, asset_server: &AssetServer, dt: f32) {
+    for (_id, (sprite, animator)) in world.query_mut::<(&mut Sprite, &mut Animator)>() {
+        let Some(sheet) = asset_server.get_sprite_sheet(sprite.asset_id) else {
+            continue;
+        };
+        let tag = animator.tag.as_deref().and_then(|name| sheet.tag(name));
+        // The sheet was reloaded with fewer frames
+        if sheet.frame_at(tag, animator.step).is_none() {
+            animator.step = 0;
+        }
+
+        animator.elapsed += dt;
+        while !animator.finished {
+            let Some(frame) = sheet.frame_at(tag, animator.step) else {
+                break;
+            };
+            let duration = match sheet.durations.get(frame).copied() {
+                Some(duration) if duration > 0.0 => duration,
+                _ => DEFAULT_FRAME_DURATION,
+            };
+            if animator.elapsed < duration {
+                break;
+            }
+            animator.elapsed -= duration;
+            animator.step += 1;
+            if sheet.frame_at(tag, animator.step).is_none() {
+                if animator.looping {
+                    animator.step = 0;
+                } else {
+                    animator.step -= 1;
+                    animator.finished = true;
+                }
+            }
+        }
+
+        if let Some(frame) = sheet.frame_at(tag, animator.step) {
+            sprite.source = sheet.frames.get(frame).copied();
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! Reader for Aseprite `.ase` / `.aseprite` files.
+//!
+//! Only what a sprite needs is read: each frame is flattened to RGBA,
+//! visible layers composed over each other, with frame durations and tags.
+//! Format: https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_GROUP: u16 = 1;
+/// Header flag: layer opacity is stored, older files ignore it.
+const HEADER_LAYER_OPACITY: u32 = 1;
+
+const CEL_RAW: u16 = 0;
+const CEL_LINKED: u16 = 1;
+const CEL_COMPRESSED: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagDirection {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+/// Named range of frames, like `walk` or `death`.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    /// First and last frames, both included.
+    pub from: usize,
+    pub to: usize,
+    pub direction: TagDirection,
+}
+
+/// A flattened Aseprite file.
+pub struct AsepriteFile {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA pixels of each frame, `width * height * 4` bytes each.
+    pub frames: Vec<Vec<u8>>,
+    /// Duration of each frame, in seconds.
+    pub durations: Vec<f32>,
+    pub tags: Vec<Tag>,
+}
+
+struct Layer {
+    visible: bool,
+    group: bool,
+    child_level: u16,
+    opacity: u8,
+}
+
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    z_index: i16,
+    width: u32,
+    height: u32,
+    /// Pixels in the file color depth.
+    pixels: Vec<u8>,
+}
+
+/// Little endian reader over a byte slice.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, cursor: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let chunk = self
+            .bytes
+            .get(self.cursor..self.cursor + count)
+            .ok_or_else(|| format!("truncated at byte {}", self.cursor))?;
+        self.cursor += count;
+        Ok(chunk)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> {
+        self.take(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+impl AsepriteFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut header = Reader::new(bytes);
+        header.skip(4)?;
+        if header.u16()? != FILE_MAGIC {
+            return Err("not an Aseprite file".to_owned());
+        }
+        let frame_count = header.u16()? as usize;
+        let width = header.u16()? as u32;
+        let height = header.u16()? as u32;
+        let depth = header.u16()?;
+        let flags = header.u32()?;
+        header.skip(10)?;
+        let transparent_index = header.u8()?;
+        if !matches!(depth, 8 | 16 | 32) {
+            return Err(format!("unsupported color depth {}", depth));
+        }
+
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut palette = vec![[0u8; 4]; 256];
+        let mut tags = Vec::new();
+        let mut frame_cels: Vec<Vec<Cel>> = Vec::with_capacity(frame_count);
+        let mut durations = Vec::with_capacity(frame_count);
+
+        let mut offset = 128;
+        for frame_index in 0..frame_count {
+            let mut frame = Reader::new(bytes.get(offset..).unwrap_or_default());
+            let frame_size = frame.u32()? as usize;
+            if frame.u16()? != FRAME_MAGIC {
+                return Err(format!("bad magic for frame {}", frame_index));
+            }
+            let old_chunk_count = frame.u16()? as usize;
+            durations.push(frame.u16()? as f32 / 1000.0);
+            frame.skip(2)?;
+            let chunk_count = match frame.u32()? as usize {
+                0 => old_chunk_count,
+                count => count,
+            };
+
+            let mut cels = Vec::new();
+            for _ in 0..chunk_count {
+                let chunk_start = frame.cursor;
+                let chunk_size = frame.u32()? as usize;
+                let chunk_type = frame.u16()?;
+                let mut chunk = Reader::new(frame.take(chunk_size.saturating_sub(6))?);
+                match chunk_type {
+                    CHUNK_LAYER => layers.push(read_layer(&mut chunk, flags)?),
+                    CHUNK_CEL => {
+                        if let Some(cel) = read_cel(&mut chunk, depth, &frame_cels)? {
+                            cels.push(cel);
+                        }
+                    }
+                    CHUNK_PALETTE => read_palette(&mut chunk, &mut palette)?,
+                    CHUNK_OLD_PALETTE => read_old_palette(&mut chunk, &mut palette)?,
+                    CHUNK_TAGS => tags = read_tags(&mut chunk)?,
+                    _ => {}
+                }
+                frame.cursor = chunk_start + chunk_size;
+            }
+            frame_cels.push(cels);
+            offset += frame_size;
+        }
+
+        if depth == 8 {
+            palette[transparent_index as usize] = [0, 0, 0, 0];
+        }
+
+        let visible = visible_layers(&layers);
+        let frames = frame_cels
+            .iter()
+            .map(|cels| compose_frame(cels, &layers, &visible, depth, &palette, width, height))
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            frames,
+            durations,
+            tags,
+        })
+    }
+}
+
+fn read_layer(chunk: &mut Reader, header_flags: u32) -> Result<Layer, String> {
+    let flags = chunk.u16()?;
+    let layer_type = chunk.u16()?;
+    let child_level = chunk.u16()?;
+    chunk.skip(6)?;
+    let opacity = chunk.u8()?;
+    Ok(Layer {
+        visible: flags & LAYER_VISIBLE != 0,
+        group: layer_type == LAYER_GROUP,
+        child_level,
+        opacity: if header_flags & HEADER_LAYER_OPACITY != 0 {
+            opacity
+        } else {
+            255
+        },
+    })
+}
+
+/// Read a cel, `None` for the tilemap cels which are not supported.
+fn read_cel(chunk: &mut Reader, depth: u16, frames: &[Vec<Cel>]) -> Result<Option<Cel>, String> {
+    let layer = chunk.u16()? as usize;
+    let x = chunk.i16()? as i32;
+    let y = chunk.i16()? as i32;
+    let opacity = chunk.u8()?;
+    let cel_type = chunk.u16()?;
+    let z_index = chunk.i16()?;
+    chunk.skip(5)?;
+
+    let (width, height, pixels) = match cel_type {
+        CEL_RAW | CEL_COMPRESSED => {
+            let width = chunk.u16()? as u32;
+            let height = chunk.u16()? as u32;
+            let size = (width * height) as usize * (depth / 8) as usize;
+            let data = chunk.take(chunk.bytes.len() - chunk.cursor)?;
+            let pixels = if cel_type == CEL_RAW {
+                data.get(..size).ok_or("truncated cel")?.to_vec()
+            } else {
+                let mut pixels = Vec::with_capacity(size);
+                ZlibDecoder::new(data)
+                    .read_to_end(&mut pixels)
+                    .map_err(|e| format!("cel: {}", e))?;
+                pixels
+            };
+            if pixels.len() < size {
+                return Err("truncated cel".to_owned());
+            }
+            (width, height, pixels)
+        }
+        CEL_LINKED => {
+            let linked_frame = chunk.u16()? as usize;
+            let linked = frames
+                .get(linked_frame)
+                .and_then(|cels| cels.iter().find(|cel| cel.layer == layer))
+                .ok_or_else(|| format!("cel linked to missing frame {}", linked_frame))?;
+            (linked.width, linked.height, linked.pixels.clone())
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Cel {
+        layer,
+        x,
+        y,
+        opacity,
+        z_index,
+        width,
+        height,
+        pixels,
+    }))
+}
+
+fn read_palette(chunk: &mut Reader, palette: &mut [[u8; 4]]) -> Result<(), String> {
+    chunk.skip(4)?;
+    let first = chunk.u32()? as usize;
+    let last = chunk.u32()? as usize;
+    chunk.skip(8)?;
+    for index in first..=last {
+        let flags = chunk.u16()?;
+        let color = chunk.take(4)?;
+        if let Some(entry) = palette.get_mut(index) {
+            entry.copy_from_slice(color);
+        }
+        // Has name
+        if flags & 1 != 0 {
+            chunk.string()?;
+        }
+    }
+    Ok(())
+}
+
+fn read_old_palette(chunk: &mut Reader, palette: &mut [[u8; 4]]) -> Result<(), String> {
+    let packets = chunk.u16()?;
+    let mut index = 0;
+    for _ in 0..packets {
+        index += chunk.u8()? as usize;
+        let count = match chunk.u8()? {
+            0 => 256,
+            count => count as usize,
+        };
+        for _ in 0..count {
+            let rgb = chunk.take(3)?;
+            if let Some(entry) = palette.get_mut(index) {
+                *entry = [rgb[0], rgb[1], rgb[2], 255];
+            }
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+fn read_tags(chunk: &mut Reader) -> Result<Vec<Tag>, String> {
+    let count = chunk.u16()?;
+    chunk.skip(8)?;
+    let mut tags = Vec::new();
+    for _ in 0..count {
+        let from = chunk.u16()? as usize;
+        let to = chunk.u16()? as usize;
+        let direction = match chunk.u8()? {
+            1 => TagDirection::Reverse,
+            2 => TagDirection::PingPong,
+            3 => TagDirection::PingPongReverse,
+            _ => TagDirection::Forward,
+        };
+        chunk.skip(2 + 6 + 3 + 1)?;
+        let name = chunk.string()?;
+        tags.push(Tag {
+            name,
+            from,
+            to,
+            direction,
+        });
+    }
+    Ok(tags)
+}
+
+/// A layer is drawn only if it and all its parent groups are visible.
+fn visible_layers(layers: &[Layer]) -> Vec<bool> {
+    let mut parents_visible: Vec<bool> = Vec::new();
+    layers
+        .iter()
+        .map(|layer| {
+            parents_visible.truncate(layer.child_level as usize);
+            let visible = layer.visible && parents_visible.iter().all(|visible| *visible);
+            if layer.group {
+                parents_visible.push(visible);
+            }
+            visible && !layer.group
+        })
+        .collect()
+}
+
+/// Compose the cels of a frame over a transparent canvas, with normal blending.
+fn compose_frame(
+    cels: &[Cel],
+    layers: &[Layer],
+    visible: &[bool],
+    depth: u16,
+    palette: &[[u8; 4]],
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let mut canvas = vec![0u8; (width * height * 4) as usize];
+
+    let mut ordered: Vec<&Cel> = cels
+        .iter()
+        .filter(|cel| visible.get(cel.layer).copied().unwrap_or(false))
+        .collect();
+    // Same order as Aseprite: by layer shifted by z-index, then by z-index
+    ordered.sort_by_key(|cel| (cel.layer as i32 + cel.z_index as i32, cel.z_index));
+
+    for cel in ordered {
+        let layer_opacity = layers[cel.layer].opacity as u32;
+        let opacity = layer_opacity * cel.opacity as u32 / 255;
+        for cy in 0..cel.height {
+            for cx in 0..cel.width {
+                let (x, y) = (cel.x + cx as i32, cel.y + cy as i32);
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+                let index = (cy * cel.width + cx) as usize;
+                let [r, g, b, a] = match depth {
+                    32 => cel.pixels[index * 4..index * 4 + 4].try_into().unwrap(),
+                    16 => {
+                        let value = cel.pixels[index * 2];
+                        [value, value, value, cel.pixels[index * 2 + 1]]
+                    }
+                    _ => palette[cel.pixels[index] as usize],
+                };
+
+                let src_alpha = a as u32 * opacity / 255;
+                if src_alpha == 0 {
+                    continue;
+                }
+                let dst = &mut canvas[((y as u32 * width + x as u32) * 4) as usize..][..4];
+                let dst_alpha = dst[3] as u32;
+                let out_alpha = src_alpha + dst_alpha * (255 - src_alpha) / 255;
+                for (channel, src) in dst.iter_mut().take(3).zip([r, g, b]) {
+                    let blended = (src as u32 * src_alpha
+                        + *channel as u32 * dst_alpha * (255 - src_alpha) / 255)
+                        / out_alpha;
+                    *channel = blended as u8;
+                }
+                dst[3] = out_alpha as u8;
+            }
+        }
+    }
+
+    canvas
+}
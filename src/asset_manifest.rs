@@ -0,0 +1,90 @@
+//! List of every asset of the game, with its type.
+//!
+//! The manifest is the only list of files to load. Code and data refer to assets by path,
+//! `validate` checks both against the manifest so a typo fails at startup instead of
+//! showing the missing texture.
+use std::path::Path;
+
+use log::info;
+use serde::Deserialize;
+
+pub const MANIFEST_PATH: &str = "assets/manifest.ron";
+
+/// What an asset loads into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AssetKind {
+    /// Image or Aseprite file.
+    Texture,
+    Sound,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub kind: AssetKind,
+    /// Loaded after the game started. Code using it must handle it not being there yet.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AssetManifest {
+    pub assets: Vec<ManifestEntry>,
+}
+
+impl AssetManifest {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let manifest = ron::from_str::<AssetManifest>(&content).map_err(|e| e.to_string())?;
+        info!(
+            "Asset manifest loaded: {} assets ({})",
+            manifest.assets.len(),
+            path
+        );
+        Ok(manifest)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&ManifestEntry> {
+        self.assets.iter().find(|entry| entry.path == path)
+    }
+
+    /// Check the manifest against the assets the game uses, given as `(path, kind)`.
+    /// Every used asset must be declared with the right kind, and every declared asset must
+    /// exist and be used. Returns one message per problem.
+    pub fn validate(&self, used: &[(&str, AssetKind)]) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (path, kind) in used {
+            match self.get(path) {
+                None => errors.push(format!("{}: used as {:?} but not declared", path, kind)),
+                Some(entry) if entry.kind != *kind => errors.push(format!(
+                    "{}: used as {:?} but declared as {:?}",
+                    path, kind, entry.kind
+                )),
+                Some(_) => {}
+            }
+        }
+
+        for (index, entry) in self.assets.iter().enumerate() {
+            if self.assets[..index]
+                .iter()
+                .any(|other| other.path == entry.path)
+            {
+                errors.push(format!("{}: declared twice", entry.path));
+                continue;
+            }
+            if !Path::new(&entry.path).is_file() {
+                errors.push(format!("{}: missing file", entry.path));
+            }
+            if !used.iter().any(|(path, _kind)| *path == entry.path) {
+                errors.push(format!("{}: declared but never used", entry.path));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
@@ -1,12 +1,117 @@
-use futures::future::join_all;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
 use log::{error, info};
+use macroquad::audio::{Sound, load_sound};
 use macroquad::prelude::*;
-use std::collections::HashMap;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+
+use crate::{
+    animation::SpriteSheet,
+    aseprite::AsepriteFile,
+    asset_manifest::{AssetKind, AssetManifest},
+};
 
 pub type AssetId = u64;
 
+/// A type of asset the `AssetServer` loads.
+pub trait Asset {
+    const KIND: AssetKind;
+}
+
+impl Asset for Texture2D {
+    const KIND: AssetKind = AssetKind::Texture;
+}
+
+impl Asset for Sound {
+    const KIND: AssetKind = AssetKind::Sound;
+}
+
+/// Reference to an asset of the manifest, typed so a sound can't be drawn as a texture.
+pub struct Handle<T: Asset> {
+    path: &'static str,
+    id: AssetId,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Derives would require `T: Clone`
+impl<T: Asset> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Asset> Copy for Handle<T> {}
+
+impl<T: Asset> Handle<T> {
+    pub const fn new(path: &'static str) -> Self {
+        Self {
+            path,
+            id: AssetServer::compute_id(path),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn id(self) -> AssetId {
+        self.id
+    }
+
+    /// Path and kind, to check the handle against the manifest.
+    pub fn usage(self) -> (&'static str, AssetKind) {
+        (self.path, T::KIND)
+    }
+}
+
+/// Loaded content of an asset.
+enum LoadedAsset {
+    Texture(Texture2D, Option<SpriteSheet>),
+    Sound(Sound),
+}
+
+/// Progress of the queued assets.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    fn record(&mut self, loaded: bool) {
+        if loaded {
+            self.loaded += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    /// Assets that loaded or failed.
+    pub fn done(&self) -> usize {
+        self.loaded + self.failed
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done() >= self.total
+    }
+
+    /// Part of the assets done, in `[0, 1]`.
+    pub fn ratio(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done() as f32 / self.total as f32
+        }
+    }
+}
+
+/// An asset waiting in the loading queue.
+struct PendingAsset {
+    path: String,
+    kind: AssetKind,
+    /// The game doesn't start without it.
+    required: bool,
+}
+
 /// Why using hash as asset id and not string ?
 /// cf. https://gameprogrammingpatterns.com/data-locality.html
 ///
@@ -15,7 +120,14 @@ pub type AssetId = u64;
 /// 
 pub struct AssetServer {
     textures: HashMap<AssetId, Texture2D>,
+    /// Frames and tags of the textures loaded from Aseprite files.
+    sprite_sheets: HashMap<AssetId, SpriteSheet>,
+    sounds: HashMap<AssetId, Sound>,
     missing_texture: Texture2D,
+    /// Assets waiting to be loaded by `load_pending`.
+    pending: VecDeque<PendingAsset>,
+    progress: LoadProgress,
+    required_progress: LoadProgress,
     // For debug, keep a link between ID and Path
     #[cfg(debug_assertions)]
     debug_names: HashMap<AssetId, String>,
@@ -28,16 +140,28 @@ impl AssetServer {
 
         Self {
             textures: HashMap::new(),
+            sprite_sheets: HashMap::new(),
+            sounds: HashMap::new(),
             missing_texture,
+            pending: VecDeque::new(),
+            progress: LoadProgress::default(),
+            required_progress: LoadProgress::default(),
             #[cfg(debug_assertions)]
             debug_names: HashMap::new(),
         }
     }
 
-    pub fn compute_id(path: &str) -> AssetId {
-        let mut hasher = DefaultHasher::new();
-        path.hash(&mut hasher);
-        hasher.finish()
+    /// FNV-1a hash of the path, `const` so handles can be constants.
+    pub const fn compute_id(path: &str) -> AssetId {
+        let bytes = path.as_bytes();
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut i = 0;
+        while i < bytes.len() {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+            i += 1;
+        }
+        hash
     }
 
     pub fn get_texture(&self, id: AssetId) -> &Texture2D {
@@ -60,45 +184,179 @@ impl AssetServer {
         }
     }
 
-    pub async fn load_assets(&mut self, paths: &[&str]) {
-        let mut futures = Vec::new();
+    pub fn get_sound(&self, handle: Handle<Sound>) -> Option<&Sound> {
+        self.sounds.get(&handle.id())
+    }
+
+    /// Returns `true` if the asset at `path` loaded, whatever its kind.
+    pub fn is_loaded(&self, path: &str) -> bool {
+        let id = Self::compute_id(path);
+        self.textures.contains_key(&id) || self.sounds.contains_key(&id)
+    }
+
+    /// The texture, if it loaded. For assets that may still be loading.
+    pub fn find_texture(&self, id: AssetId) -> Option<&Texture2D> {
+        self.textures.get(&id)
+    }
+
+    pub fn get_sprite_sheet(&self, id: AssetId) -> Option<&SpriteSheet> {
+        self.sprite_sheets.get(&id)
+    }
+
+    /// Progress of every queued asset.
+    pub fn progress(&self) -> LoadProgress {
+        self.progress
+    }
+
+    /// Progress of the assets the game needs to start.
+    pub fn required_progress(&self) -> LoadProgress {
+        self.required_progress
+    }
+
+    /// Load an Aseprite file, flattened and packed in a single texture.
+    async fn load_aseprite(path: &str) -> Result<(Texture2D, SpriteSheet), String> {
+        let bytes = load_file(path).await.map_err(|e| e.to_string())?;
+        let file = AsepriteFile::parse(&bytes)?;
+        let (image, sheet) = SpriteSheet::pack(&file);
+        Ok((Texture2D::from_image(&image), sheet))
+    }
+
+    async fn load_asset(path: &str, kind: AssetKind) -> Result<LoadedAsset, String> {
+        match kind {
+            AssetKind::Texture if path.ends_with(".ase") || path.ends_with(".aseprite") => {
+                Self::load_aseprite(path)
+                    .await
+                    .map(|(texture, sheet)| LoadedAsset::Texture(texture, Some(sheet)))
+            }
+            AssetKind::Texture => load_texture(path)
+                .await
+                .map(|texture| LoadedAsset::Texture(texture, None))
+                .map_err(|e| e.to_string()),
+            AssetKind::Sound => load_sound(path)
+                .await
+                .map(LoadedAsset::Sound)
+                .map_err(|e| e.to_string()),
+        }
+    }
 
-        for path in paths {
-            let path_owned = path.to_string();
-            futures.push(async move {
-                let result = load_texture(&path_owned).await;
-                let id = AssetServer::compute_id(&path_owned);
-                (id, path_owned, result)
+    /// Queue every asset of the manifest, the required ones first.
+    pub fn queue_manifest(&mut self, manifest: &AssetManifest) {
+        let (required, optional): (Vec<_>, Vec<_>) =
+            manifest.assets.iter().partition(|entry| !entry.optional);
+        for entry in required.into_iter().chain(optional) {
+            self.pending.push_back(PendingAsset {
+                path: entry.path.clone(),
+                kind: entry.kind,
+                required: !entry.optional,
             });
+            self.progress.total += 1;
+            if !entry.optional {
+                self.required_progress.total += 1;
+            }
         }
+    }
 
-        let results = join_all(futures).await;
+    /// Load queued assets for about `budget` seconds, at least one.
+    /// Called once per frame, so the window keeps drawing while loading.
+    pub async fn load_pending(&mut self, budget: f64) {
+        let start = get_time();
+        while let Some(pending) = self.pending.pop_front() {
+            let result = Self::load_asset(&pending.path, pending.kind).await;
+            let loaded = self.store(&pending.path, result);
+            self.progress.record(loaded);
+            if pending.required {
+                self.required_progress.record(loaded);
+            }
+            info!(
+                "Assets: {}/{} done",
+                self.progress.done(),
+                self.progress.total
+            );
+            if get_time() - start >= budget {
+                break;
+            }
+        }
+    }
 
-        for (id, path, result) in results {
-            match result {
-                Ok(texture) => {
-                    texture.set_filter(FilterMode::Nearest);
-                    self.textures.insert(id, texture);
+    /// Load the assets at `paths` right away. Loading a path again replaces its asset under the
+    /// same id, a failure keeps the previous one.
+    pub async fn load_assets(&mut self, paths: &[(&str, AssetKind)]) {
+        let mut futures: FuturesUnordered<_> = paths
+            .iter()
+            .map(|&(path, kind)| async move { (path, Self::load_asset(path, kind).await) })
+            .collect();
 
-                    #[cfg(debug_assertions)]
-                    self.debug_names.insert(id, path.clone());
+        while let Some((path, result)) = futures.next().await {
+            self.store(path, result);
+        }
+    }
 
-                    info!("Asset Loaded: {} -> ID: {}", path, id);
+    /// Keep a loaded asset, or log why it failed. Returns `true` if it loaded.
+    fn store(&mut self, path: &str, result: Result<LoadedAsset, String>) -> bool {
+        let id = Self::compute_id(path);
+        match result {
+            Ok(asset) => {
+                match asset {
+                    LoadedAsset::Texture(texture, sheet) => {
+                        texture.set_filter(FilterMode::Nearest);
+                        self.textures.insert(id, texture);
+                        if let Some(sheet) = sheet {
+                            self.sprite_sheets.insert(id, sheet);
+                        }
+                    }
+                    LoadedAsset::Sound(sound) => {
+                        self.sounds.insert(id, sound);
+                    }
                 }
-                Err(e) => error!("Loading asset {}: {}", path, e),
+
+                #[cfg(debug_assertions)]
+                self.debug_names.insert(id, path.to_owned());
+
+                info!("Asset Loaded: {} -> ID: {}", path, id);
+                true
+            }
+            Err(e) => {
+                error!("Loading asset {}: {}", path, e);
+                false
             }
         }
     }
 }
 
+/// Assets used by the code. Data files name theirs by path.
 pub mod assets {
-    use super::AssetId;
-    use super::AssetServer;
+    use macroquad::{audio::Sound, texture::Texture2D};
 
-    pub fn player() -> AssetId {
-        AssetServer::compute_id("assets/player.png")
-    }
-    pub fn enemy() -> AssetId {
-        AssetServer::compute_id("assets/enemy.png")
+    use super::Handle;
+    use crate::asset_manifest::AssetKind;
+
+    pub const PLAYER: Handle<Texture2D> = Handle::new("assets/player.ase");
+    pub const FIREBALL: Handle<Texture2D> = Handle::new("assets/projectiles/fireball.aseprite");
+    pub const GEM: Handle<Texture2D> = Handle::new("assets/pickups/gem.png");
+
+    pub const HIT: Handle<Sound> = Handle::new("assets/sounds/hit.wav");
+    pub const HURT: Handle<Sound> = Handle::new("assets/sounds/hurt.wav");
+    pub const KILL: Handle<Sound> = Handle::new("assets/sounds/kill.wav");
+    pub const PICKUP: Handle<Sound> = Handle::new("assets/sounds/pickup.wav");
+    pub const LEVEL_UP: Handle<Sound> = Handle::new("assets/sounds/level_up.wav");
+    pub const DEATH: Handle<Sound> = Handle::new("assets/sounds/death.wav");
+    pub const TITLE_MUSIC: Handle<Sound> = Handle::new("assets/sounds/music/title.wav");
+    pub const BATTLE_MUSIC: Handle<Sound> = Handle::new("assets/sounds/music/battle.wav");
+
+    /// Every handle above, checked against the manifest at startup.
+    pub fn usages() -> Vec<(&'static str, AssetKind)> {
+        vec![
+            PLAYER.usage(),
+            FIREBALL.usage(),
+            GEM.usage(),
+            HIT.usage(),
+            HURT.usage(),
+            KILL.usage(),
+            PICKUP.usage(),
+            LEVEL_UP.usage(),
+            DEATH.usage(),
+            TITLE_MUSIC.usage(),
+            BATTLE_MUSIC.usage(),
+        ]
     }
 }
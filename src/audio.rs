@@ -0,0 +1,388 @@
+//! Sound effects and music.
+//!
+//! Sounds are played from the gameplay events of each frame, never from the simulation itself.
+//! Without the `audio` feature (on by default) nothing is played, the manager still tracks its
+//! state.
+use std::{collections::HashMap, path::PathBuf};
+
+use hecs::Entity;
+use log::{error, info};
+use macroquad::audio::{PlaySoundParams, Sound, play_sound, set_sound_volume, stop_sound};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    asset_server::{AssetId, AssetServer, Handle, assets},
+    events::{
+        DamageDealt, EnemyKilled, EventCursor, Events, Item, ItemPicked, PlayerDied,
+        PlayerLeveledUp,
+    },
+    schedule::ResourceLayers,
+    state::GameState,
+};
+
+/// Name of the volume settings file, in the user config directory.
+pub const SETTINGS_FILE: &str = "audio.ron";
+
+/// Positional sounds closer than this to the listener play at full volume.
+const FULL_VOLUME_DISTANCE: f32 = 400.0;
+/// Positional sounds further than this are not played.
+const SILENT_DISTANCE: f32 = 1000.0;
+/// Duration of the crossfade between two music tracks, in seconds.
+const CROSSFADE_DURATION: f32 = 1.5;
+/// Volume change per press in the pause menu.
+const VOLUME_STEP: f32 = 0.1;
+
+/// The volumes the player can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Volume {
+    Master,
+    Music,
+    Sfx,
+}
+
+impl Volume {
+    pub const ALL: [Volume; 3] = [Volume::Master, Volume::Music, Volume::Sfx];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Volume::Master => "Master volume",
+            Volume::Music => "Music volume",
+            Volume::Sfx => "Effects volume",
+        }
+    }
+}
+
+/// Volumes in `[0, 1]`. Music and effects are scaled by the master volume.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 0.6,
+            sfx: 0.8,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// Load the settings. A missing or invalid file gives the defaults.
+    pub fn load(path: &PathBuf) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => match ron::from_str::<AudioSettings>(&content) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    error!("Loading audio settings {}: {}", path.display(), e);
+                    Self::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!(
+                    "No audio settings at {}, using the defaults",
+                    path.display()
+                );
+                Self::default()
+            }
+            Err(e) => {
+                error!("Loading audio settings {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+
+    pub fn volume(&self, volume: Volume) -> f32 {
+        match volume {
+            Volume::Master => self.master,
+            Volume::Music => self.music,
+            Volume::Sfx => self.sfx,
+        }
+    }
+
+    /// Raise `volume` by `steps` steps, or lower it with negative steps, within `[0, 1]`.
+    pub fn change(&mut self, volume: Volume, steps: i32) {
+        let value = match volume {
+            Volume::Master => &mut self.master,
+            Volume::Music => &mut self.music,
+            Volume::Sfx => &mut self.sfx,
+        };
+        // Rounded to the step, so repeated changes don't drift
+        let changed = (*value / VOLUME_STEP).round() + steps as f32;
+        *value = (changed * VOLUME_STEP).clamp(0.0, 1.0);
+    }
+}
+
+/// A one-shot sound, and how many copies of it may play at once.
+struct SoundEffect {
+    sound: Handle<Sound>,
+    /// Plays over this limit are dropped, so hundreds of hits in a tick don't clip.
+    max_voices: usize,
+    /// Length of the sound in seconds, a voice is busy for that long.
+    duration: f32,
+    volume: f32,
+}
+
+const ENEMY_HIT: SoundEffect = SoundEffect {
+    sound: assets::HIT,
+    max_voices: 4,
+    duration: 0.09,
+    volume: 0.5,
+};
+const PLAYER_HIT: SoundEffect = SoundEffect {
+    sound: assets::HURT,
+    max_voices: 1,
+    duration: 0.22,
+    volume: 0.8,
+};
+const ENEMY_KILLED: SoundEffect = SoundEffect {
+    sound: assets::KILL,
+    max_voices: 3,
+    duration: 0.15,
+    volume: 0.6,
+};
+const ITEM_PICKED: SoundEffect = SoundEffect {
+    sound: assets::PICKUP,
+    max_voices: 3,
+    duration: 0.1,
+    volume: 0.5,
+};
+const TREASURE_PICKED: SoundEffect = SoundEffect {
+    sound: assets::LEVEL_UP,
+    max_voices: 1,
+    duration: 0.66,
+    volume: 1.0,
+};
+const LEVEL_UP: SoundEffect = SoundEffect {
+    sound: assets::LEVEL_UP,
+    max_voices: 1,
+    duration: 0.66,
+    volume: 0.9,
+};
+const PLAYER_DIED: SoundEffect = SoundEffect {
+    sound: assets::DEATH,
+    max_voices: 1,
+    duration: 1.0,
+    volume: 1.0,
+};
+
+/// Where the audio is in each event queue.
+#[derive(Default)]
+struct EventCursors {
+    damage_dealt: EventCursor,
+    enemy_killed: EventCursor,
+    item_picked: EventCursor,
+    player_leveled_up: EventCursor,
+    player_died: EventCursor,
+}
+
+/// Music of each screen, `None` for silence.
+pub fn music_for(state: GameState) -> Option<Handle<Sound>> {
+    match state {
+        GameState::Loading | GameState::GameOver => None,
+        GameState::Title => Some(assets::TITLE_MUSIC),
+        GameState::Playing | GameState::Paused | GameState::Controls | GameState::LevelUp => {
+            Some(assets::BATTLE_MUSIC)
+        }
+    }
+}
+
+struct MusicTrack {
+    sound: Handle<Sound>,
+    /// Crossfade level in `[0, 1]`, before the volume settings.
+    fade: f32,
+    /// Started, it may still be loading.
+    playing: bool,
+}
+
+pub struct AudioManager {
+    pub settings: AudioSettings,
+    enabled: bool,
+    /// Time since the start of the game, in seconds.
+    time: f32,
+    /// End time of the voices of each one-shot.
+    voices: HashMap<AssetId, Vec<f32>>,
+    music: Option<MusicTrack>,
+    /// Previous tracks, fading out.
+    fading_out: Vec<MusicTrack>,
+    cursors: EventCursors,
+}
+
+impl AudioManager {
+    pub fn new(settings: AudioSettings) -> Self {
+        Self {
+            settings,
+            enabled: cfg!(feature = "audio"),
+            time: 0.0,
+            voices: HashMap::new(),
+            music: None,
+            fading_out: Vec::new(),
+            cursors: EventCursors::default(),
+        }
+    }
+
+    /// Forget the events of the previous run, the new one sends its own from the start.
+    pub fn start_run(&mut self) {
+        self.cursors = EventCursors::default();
+    }
+
+    /// Play the sounds of the events sent since the last call, heard from `listener`.
+    /// The event queues are resources of the run.
+    pub fn play_events(
+        &mut self,
+        events: &ResourceLayers,
+        listener: Vec2,
+        player: Option<Entity>,
+        asset_server: &AssetServer,
+    ) {
+        // Sounds heard everywhere have no position
+        let mut shots: Vec<(&SoundEffect, Option<Vec2>)> = Vec::new();
+        let cursors = &mut self.cursors;
+        let damage_dealt = events.get::<Events<DamageDealt>>();
+        for hit in damage_dealt.read(&mut cursors.damage_dealt) {
+            if Some(hit.target) == player {
+                shots.push((&PLAYER_HIT, None));
+            } else {
+                shots.push((&ENEMY_HIT, Some(hit.position)));
+            }
+        }
+        let enemy_killed = events.get::<Events<EnemyKilled>>();
+        for killed in enemy_killed.read(&mut cursors.enemy_killed) {
+            shots.push((&ENEMY_KILLED, Some(killed.position)));
+        }
+        let item_picked = events.get::<Events<ItemPicked>>();
+        for picked in item_picked.read(&mut cursors.item_picked) {
+            match picked.item {
+                Item::XpGem { .. } => shots.push((&ITEM_PICKED, Some(picked.position))),
+                Item::Treasure { .. } => shots.push((&TREASURE_PICKED, None)),
+            }
+        }
+        let player_leveled_up = events.get::<Events<PlayerLeveledUp>>();
+        for _level_up in player_leveled_up.read(&mut cursors.player_leveled_up) {
+            shots.push((&LEVEL_UP, None));
+        }
+        let player_died = events.get::<Events<PlayerDied>>();
+        for died in player_died.read(&mut cursors.player_died) {
+            shots.push((&PLAYER_DIED, Some(died.position)));
+        }
+
+        for (effect, position) in shots {
+            let falloff = position.map_or(1.0, |position| falloff(listener, position));
+            self.play_one_shot(effect, falloff, asset_server);
+        }
+    }
+
+    fn play_one_shot(&mut self, effect: &SoundEffect, falloff: f32, asset_server: &AssetServer) {
+        if !self.enabled || falloff <= 0.0 {
+            return;
+        }
+        let Some(sound) = asset_server.get_sound(effect.sound) else {
+            return;
+        };
+
+        let time = self.time;
+        let voices = self.voices.entry(effect.sound.id()).or_default();
+        voices.retain(|end| *end > time);
+        if voices.len() >= effect.max_voices {
+            return;
+        }
+        voices.push(time + effect.duration);
+
+        play_sound(
+            sound,
+            PlaySoundParams {
+                looped: false,
+                volume: effect.volume * falloff * self.settings.sfx * self.settings.master,
+            },
+        );
+    }
+
+    /// Crossfade to `music`, or fade out with `None`. Does nothing if it is already playing.
+    pub fn play_music(&mut self, music: Option<Handle<Sound>>) {
+        let current = self.music.as_ref().map(|track| track.sound.id());
+        if current == music.map(Handle::id) {
+            return;
+        }
+
+        self.fading_out.extend(self.music.take());
+        self.music = music.map(|sound| {
+            // Switching back to a track still fading out picks it up where it is
+            match self
+                .fading_out
+                .iter()
+                .position(|track| track.sound.id() == sound.id())
+            {
+                Some(index) => self.fading_out.remove(index),
+                None => MusicTrack {
+                    sound,
+                    fade: 0.0,
+                    playing: false,
+                },
+            }
+        });
+    }
+
+    /// Advance the crossfades. Call once per frame.
+    pub fn update(&mut self, dt: f32, asset_server: &AssetServer) {
+        self.time += dt;
+        if !self.enabled {
+            return;
+        }
+        let step = dt / CROSSFADE_DURATION;
+        let volume = self.settings.music * self.settings.master;
+
+        if let Some(track) = self.music.as_mut() {
+            // Music is optional, it starts whenever it finished loading
+            if let Some(sound) = asset_server.get_sound(track.sound) {
+                track.fade = (track.fade + step).min(1.0);
+                if !track.playing {
+                    play_sound(
+                        sound,
+                        PlaySoundParams {
+                            looped: true,
+                            volume: track.fade * volume,
+                        },
+                    );
+                    track.playing = true;
+                }
+                set_sound_volume(sound, track.fade * volume);
+            }
+        }
+
+        self.fading_out.retain_mut(|track| {
+            let Some(sound) = asset_server.get_sound(track.sound) else {
+                return false;
+            };
+            track.fade = (track.fade - step).max(0.0);
+            if track.fade <= 0.0 {
+                if track.playing {
+                    stop_sound(sound);
+                }
+                return false;
+            }
+            set_sound_volume(sound, track.fade * volume);
+            true
+        });
+    }
+}
+
+/// Volume factor of a sound at `position`, heard from `listener`.
+fn falloff(listener: Vec2, position: Vec2) -> f32 {
+    let distance = listener.distance(position);
+    1.0 - ((distance - FULL_VOLUME_DISTANCE) / (SILENT_DISTANCE - FULL_VOLUME_DISTANCE))
+        .clamp(0.0, 1.0)
+}
@@ -0,0 +1,229 @@
+//! Bosses: enemies with a large health pool, going through attack phases as they lose health.
+//!
+//! A boss is an enemy definition with a `boss` section, spawned by the `Director` from the
+//! `bosses` of the timeline. It moves as its `AiBehaviour` says, and fires the attacks of its
+//! current phase in turn. It always drops a treasure.
+use std::f32::consts::TAU;
+
+use hecs::{Entity, World};
+use log::{info, warn};
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    components::{Despawn, Enemy, GameRng, Health, Player},
+    enemy::{EnemyDefinition, EnemyRegistry, spawn_around, spawn_enemy, spawn_enemy_projectile},
+    physic::{ColliderHandleComponent, PhysicsResources, collider_center},
+    progression::spawn_treasure,
+};
+
+/// Distance from the boss center at which summoned enemies appear.
+const SUMMON_DISTANCE: f32 = 96.0;
+
+/// A bullet pattern or summon fired by a boss.
+#[derive(Debug, Clone, Deserialize)]
+pub enum AttackPattern {
+    /// `count` projectiles, evenly spread around the boss.
+    RadialBurst { count: u32, speed: f32 },
+    /// `arms` projectiles evenly spread, turned `turn` radians further at each spiral.
+    /// A phase firing only spirals, at a short interval, draws spinning arms.
+    Spiral { arms: u32, turn: f32, speed: f32 },
+    /// `count` projectiles at the player, `spread` radians apart.
+    AimedVolley { count: u32, spread: f32, speed: f32 },
+    /// `count` enemies of the `enemy` definition, around the boss.
+    Summon { enemy: String, count: u32 },
+}
+
+/// A stage of the fight.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BossPhase {
+    /// The phase starts once the boss health falls to this fraction of its maximum.
+    /// The first phase starts at spawn.
+    pub health_threshold: f32,
+    /// Seconds between two attacks.
+    pub interval: f32,
+    /// Attacks of the phase, fired in turn.
+    pub attacks: Vec<AttackPattern>,
+}
+
+/// The `boss` section of an enemy definition.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BossDefinition {
+    /// Phases of the fight, by decreasing health threshold.
+    pub phases: Vec<BossPhase>,
+    /// Damage of the boss projectiles.
+    pub projectile_damage: f32,
+    /// Upgrades granted by the treasure it drops.
+    pub treasure: u32,
+}
+
+/// A boss, and where it is in its fight.
+pub struct Boss {
+    pub name: String,
+    pub definition: BossDefinition,
+    /// Index of the current phase in `definition.phases`.
+    pub phase: usize,
+    /// Seconds before the next attack.
+    pub timer: f32,
+    /// Index of the next attack in the phase, wrapping around.
+    pub next_attack: usize,
+    /// Angle of the first spiral arm, turning at each spiral.
+    pub spiral_angle: f32,
+}
+
+/// Spawn the boss of `definition` centered on `center`, its health multiplied by
+/// `health_multiplier`. Its body is a regular enemy of the definition.
+pub fn spawn_boss(
+    world: &mut World,
+    rng: &mut GameRng,
+    definition: &EnemyDefinition,
+    center: Vec2,
+    health_multiplier: f32,
+) -> Entity {
+    let boss = spawn_enemy(world, rng, definition, center);
+    let boss_definition = definition.boss.clone().unwrap_or_else(|| {
+        warn!(
+            "Boss {} has no boss section, it won't attack",
+            definition.name
+        );
+        BossDefinition::default()
+    });
+
+    if let Ok(mut health) = world.get::<&mut Health>(boss) {
+        health.max *= health_multiplier;
+        health.actual = health.max;
+    }
+    let timer = boss_definition
+        .phases
+        .first()
+        .map_or(0.0, |phase| phase.interval);
+    let _ = world.insert_one(
+        boss,
+        Boss {
+            name: definition.name.clone(),
+            definition: boss_definition,
+            phase: 0,
+            timer,
+            next_attack: 0,
+            spiral_angle: 0.0,
+        },
+    );
+    boss
+}
+
+/// Move bosses to the phase of their health, and fire their attacks.
+pub fn boss_system(
+    world: &mut World,
+    rng: &mut GameRng,
+    physics: &PhysicsResources,
+    registry: &EnemyRegistry,
+    dt: f32,
+) {
+    let Some(player_center) = world
+        .query::<&ColliderHandleComponent>()
+        .with::<&Player>()
+        .iter()
+        .find_map(|(_id, handle)| collider_center(physics, handle))
+    else {
+        return;
+    };
+
+    let mut shots = Vec::new();
+    let mut summons = Vec::new();
+    for (_id, (boss, health, handle)) in world
+        .query_mut::<(&mut Boss, &Health, &ColliderHandleComponent)>()
+        .without::<&Despawn>()
+    {
+        let Some(center) = collider_center(physics, handle) else {
+            continue;
+        };
+
+        // A big hit can skip phases
+        let ratio = health.actual / health.max;
+        while boss
+            .definition
+            .phases
+            .get(boss.phase + 1)
+            .is_some_and(|next| ratio <= next.health_threshold)
+        {
+            boss.phase += 1;
+            boss.next_attack = 0;
+            boss.timer = boss.definition.phases[boss.phase].interval;
+            info!("Boss {} enters phase {}", boss.name, boss.phase + 1);
+        }
+
+        let Some(phase) = boss.definition.phases.get(boss.phase) else {
+            continue;
+        };
+        boss.timer -= dt;
+        if boss.timer > 0.0 || phase.attacks.is_empty() {
+            continue;
+        }
+        boss.timer = phase.interval;
+        let attack = phase.attacks[boss.next_attack % phase.attacks.len()].clone();
+        boss.next_attack += 1;
+
+        let damage = boss.definition.projectile_damage;
+        match attack {
+            AttackPattern::RadialBurst { count, speed } => {
+                for i in 0..count {
+                    let angle = TAU * i as f32 / count as f32;
+                    shots.push((center, Vec2::from_angle(angle) * speed, damage));
+                }
+            }
+            AttackPattern::Spiral { arms, turn, speed } => {
+                for i in 0..arms {
+                    let angle = boss.spiral_angle + TAU * i as f32 / arms as f32;
+                    shots.push((center, Vec2::from_angle(angle) * speed, damage));
+                }
+                boss.spiral_angle = (boss.spiral_angle + turn) % TAU;
+            }
+            AttackPattern::AimedVolley {
+                count,
+                spread,
+                speed,
+            } => {
+                let aim = (player_center - center).normalize_or_zero();
+                // Center the fan on the player
+                let first_angle = -spread * count.saturating_sub(1) as f32 / 2.0;
+                for i in 0..count {
+                    let direction = Vec2::from_angle(first_angle + spread * i as f32).rotate(aim);
+                    shots.push((center, direction * speed, damage));
+                }
+            }
+            AttackPattern::Summon { enemy, count } => summons.push((center, enemy, count)),
+        }
+    }
+
+    for (origin, velocity, damage) in shots {
+        spawn_enemy_projectile(world, origin, velocity, damage);
+    }
+    for (center, enemy, count) in summons {
+        match registry.get(&enemy) {
+            Some(definition) => {
+                spawn_around(world, rng, definition, center, count, SUMMON_DISTANCE)
+            }
+            None => warn!("Summoned enemy {} has no definition", enemy),
+        }
+    }
+}
+
+/// Drop a treasure where each boss killed this tick was.
+pub fn drop_treasure_system(world: &mut World, physics: &PhysicsResources) {
+    let drops: Vec<(Vec2, String, u32)> = world
+        .query::<(&Boss, &Health, &ColliderHandleComponent)>()
+        .with::<(&Enemy, &Despawn)>()
+        .iter()
+        .filter(|(_id, (_boss, health, _handle))| health.actual <= 0.0)
+        .filter_map(|(_id, (boss, _health, handle))| {
+            collider_center(physics, handle)
+                .map(|center| (center, boss.name.clone(), boss.definition.treasure))
+        })
+        .collect();
+
+    for (center, name, upgrades) in drops {
+        info!("Boss {} defeated", name);
+        // Guaranteed: even a boss without a boss section drops one upgrade
+        spawn_treasure(world, center, upgrades.max(1));
+    }
+}
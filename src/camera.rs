@@ -0,0 +1,209 @@
+//! Camera following the player, with zoom and screen shake.
+//!
+//! Only the rendering reads it directly. The simulation gets `view_half_extents` from it,
+//! so spawns stay just outside of what the player sees whatever the zoom.
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+
+use crate::{
+    components::{Player, Transform},
+    events::{DamageDealt, EventCursor, Events},
+    input::{Action, InputMap},
+    physic::{ColliderHandleComponent, PhysicsResources},
+};
+
+/// Half the width of the view at zoom 1, in world units.
+const BASE_HALF_WIDTH: f32 = 400.0;
+/// Zoom factor applied per second while a zoom action is held.
+const ZOOM_KEY_RATE: f32 = 2.0;
+/// Zoom factor applied per mouse wheel notch.
+const ZOOM_WHEEL_STEP: f32 = 1.1;
+/// Trauma lost per second, a full shake lasts one second.
+const TRAUMA_DECAY: f32 = 1.0;
+/// Offset in world units and angle in radians at full trauma.
+const MAX_SHAKE_OFFSET: f32 = 12.0;
+const MAX_SHAKE_ANGLE: f32 = 0.05;
+/// Speed of the shake noise, in oscillations per second.
+const SHAKE_FREQUENCY: f32 = 20.0;
+/// Trauma added per point of damage the player takes, a 10 damage hit adds 0.4.
+const HIT_TRAUMA_PER_DAMAGE: f32 = 0.04;
+
+pub struct CameraController {
+    /// Center of the view, in world units, without shake.
+    pub position: Vec2,
+    pub zoom: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// How fast the camera catches up with its target. Higher is snappier.
+    pub smoothing: f32,
+    /// Half the size of the area around the center where the target moves without the camera.
+    pub dead_zone: Vec2,
+    /// Area the view never leaves, if any.
+    pub bounds: Option<Rect>,
+    /// In `[0, 1]`, the shake strength is its square so small hits stay subtle.
+    trauma: f32,
+    shake_time: f32,
+    /// Hits already shaken for.
+    hit_cursor: EventCursor,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            zoom: 1.0,
+            min_zoom: 0.5,
+            max_zoom: 2.0,
+            smoothing: 8.0,
+            dead_zone: vec2(24.0, 16.0),
+            bounds: None,
+            trauma: 0.0,
+            shake_time: 0.0,
+            hit_cursor: EventCursor::default(),
+        }
+    }
+}
+
+impl CameraController {
+    /// Half the size of the area seen by the camera, in world units.
+    pub fn view_half_extents(&self) -> Vec2 {
+        let aspect_ratio = screen_width() / screen_height();
+        vec2(BASE_HALF_WIDTH, BASE_HALF_WIDTH / aspect_ratio) / self.zoom
+    }
+
+    /// Area seen by the camera, in world units, shake included.
+    pub fn view_rect(&self) -> Rect {
+        let half_extents = self.view_half_extents();
+        let center = self.position + self.shake_offset();
+        Rect::new(
+            center.x - half_extents.x,
+            center.y - half_extents.y,
+            half_extents.x * 2.0,
+            half_extents.y * 2.0,
+        )
+    }
+
+    /// Add screen shake, capped to full trauma.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    /// Shake for the hits `player` took since the last call, harder for bigger hits.
+    pub fn shake_on_hits(&mut self, hits: &Events<DamageDealt>, player: Entity) {
+        let damage: f32 = hits
+            .read(&mut self.hit_cursor)
+            .filter(|hit| hit.target == player)
+            .map(|hit| hit.amount)
+            .sum();
+        self.add_trauma(HIT_TRAUMA_PER_DAMAGE * damage);
+    }
+
+    /// Center the camera on `target` without smoothing, and forget the previous run,
+    /// when a run starts.
+    pub fn start_run(&mut self, target: Option<Vec2>) {
+        if let Some(target) = target {
+            self.position = self.clamp_to_bounds(target);
+        }
+        self.trauma = 0.0;
+        self.hit_cursor = EventCursor::default();
+    }
+
+    /// Follow `target` and apply the zoom actions for one frame.
+    pub fn update(&mut self, dt: f32, target: Option<Vec2>, input: &InputMap) {
+        let zoom_keys = input.value(Action::ZoomIn) - input.value(Action::ZoomOut);
+        let wheel = match mouse_wheel().1 {
+            0.0 => 0.0,
+            wheel => wheel.signum(),
+        };
+        self.zoom *= ZOOM_KEY_RATE.powf(zoom_keys * dt) * ZOOM_WHEEL_STEP.powf(wheel);
+        self.zoom = self.zoom.clamp(self.min_zoom, self.max_zoom);
+
+        if let Some(target) = target {
+            // Only follow the part of the offset outside of the dead zone
+            let offset = target - self.position;
+            let outside = offset - offset.clamp(-self.dead_zone, self.dead_zone);
+            // Frame rate independent exponential smoothing
+            let t = 1.0 - (-self.smoothing * dt).exp();
+            self.position += outside * t;
+        }
+        self.position = self.clamp_to_bounds(self.position);
+
+        self.trauma = (self.trauma - TRAUMA_DECAY * dt).max(0.0);
+        self.shake_time += dt;
+    }
+
+    fn clamp_to_bounds(&self, center: Vec2) -> Vec2 {
+        let Some(bounds) = self.bounds else {
+            return center;
+        };
+        let half_extents = self.view_half_extents();
+        let min = bounds.point() + half_extents;
+        let max = bounds.point() + bounds.size() - half_extents;
+        // A view larger than the bounds stays centered on them
+        vec2(
+            if min.x <= max.x {
+                center.x.clamp(min.x, max.x)
+            } else {
+                bounds.center().x
+            },
+            if min.y <= max.y {
+                center.y.clamp(min.y, max.y)
+            } else {
+                bounds.center().y
+            },
+        )
+    }
+
+    /// Smooth pseudo-random value in `[-1, 1]`, a different one for each `seed`.
+    fn shake_noise(&self, seed: f32) -> f32 {
+        let t = self.shake_time * SHAKE_FREQUENCY;
+        ((t + seed * 17.0).sin() + (t * 1.7 + seed * 31.0).sin()) / 2.0
+    }
+
+    fn shake_offset(&self) -> Vec2 {
+        let shake = self.trauma * self.trauma;
+        vec2(self.shake_noise(1.0), self.shake_noise(2.0)) * MAX_SHAKE_OFFSET * shake
+    }
+
+    pub fn camera_2d(&self) -> Camera2D {
+        let half_extents = self.view_half_extents();
+        let shake = self.trauma * self.trauma;
+        Camera2D {
+            target: self.position + self.shake_offset(),
+            zoom: vec2(1.0 / half_extents.x, 1.0 / half_extents.y),
+            rotation: (MAX_SHAKE_ANGLE * shake * self.shake_noise(3.0)).to_degrees(),
+            ..Default::default()
+        }
+    }
+
+    /// Position on screen, in pixels, of the world point `position`.
+    pub fn world_to_screen(&self, position: Vec2) -> Vec2 {
+        self.camera_2d().world_to_screen(position)
+    }
+}
+
+/// Center of the collider `handle` of an entity at `transform`, interpolated like the `Transform`.
+/// Without a collider, the position is used as is.
+pub fn interpolated_center(
+    transform: &Transform,
+    handle: Option<&ColliderHandleComponent>,
+    physics: &PhysicsResources,
+) -> Vec2 {
+    let offset = handle
+        .and_then(|handle| physics.collider_set.get(handle.0))
+        .and_then(|collider| collider.position_wrt_parent())
+        .map(|position| vec2(position.translation.x, position.translation.y))
+        .unwrap_or(Vec2::ZERO);
+    transform.position + offset
+}
+
+/// Center of the player collider, interpolated like its `Transform`.
+/// Before the first tick the player has no collider yet, its position is used as is.
+pub fn player_center(world: &World, physics: &PhysicsResources) -> Option<Vec2> {
+    world
+        .query::<(&Transform, Option<&ColliderHandleComponent>)>()
+        .with::<&Player>()
+        .iter()
+        .next()
+        .map(|(_id, (transform, handle))| interpolated_center(transform, handle, physics))
+}
@@ -0,0 +1,136 @@
+//! Physics layers, and which layers touch each other.
+//!
+//! Every collider belongs to one `PhysicsLayer`, set as a component next to its `Collider`.
+//! The `CollisionMatrix` says, for each pair of layers, whether they push each other, only
+//! report their contacts, or go through each other. It is applied when the collider enters
+//! the physics world, see `sync_physics_world`.
+use log::{error, info};
+use rapier2d::prelude::*;
+use serde::Deserialize;
+
+pub const COLLISION_MATRIX_PATH: &str = "assets/collision.ron";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PhysicsLayer {
+    Player,
+    Enemy,
+    PlayerProjectile,
+    EnemyProjectile,
+    Pickup,
+    Wall,
+}
+
+impl PhysicsLayer {
+    pub const ALL: [PhysicsLayer; 6] = [
+        PhysicsLayer::Player,
+        PhysicsLayer::Enemy,
+        PhysicsLayer::PlayerProjectile,
+        PhysicsLayer::EnemyProjectile,
+        PhysicsLayer::Pickup,
+        PhysicsLayer::Wall,
+    ];
+
+    /// Rapier group of the layer, one bit per layer.
+    pub fn group(self) -> Group {
+        Group::from_bits_truncate(1 << self as u32)
+    }
+}
+
+/// What happens when colliders of two layers touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Interaction {
+    /// The bodies push each other, and the contact is reported.
+    Solid,
+    /// The contact is reported, nothing is pushed.
+    Overlap,
+}
+
+/// Interactions between layers, loaded from `assets/collision.ron`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollisionMatrix {
+    /// Pairs of layers that interact, in any order. Layers of other pairs go through each
+    /// other, and a layer goes through itself unless paired with itself.
+    pub pairs: Vec<(PhysicsLayer, PhysicsLayer, Interaction)>,
+}
+
+impl Default for CollisionMatrix {
+    /// Everything pushes everything, as without layers.
+    fn default() -> Self {
+        let mut pairs = Vec::new();
+        for (index, a) in PhysicsLayer::ALL.iter().enumerate() {
+            for b in PhysicsLayer::ALL[index..].iter() {
+                pairs.push((*a, *b, Interaction::Solid));
+            }
+        }
+        Self { pairs }
+    }
+}
+
+impl CollisionMatrix {
+    pub fn load(path: &str) -> Self {
+        let matrix = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                ron::from_str::<CollisionMatrix>(&content).map_err(|e| e.to_string())
+            });
+
+        match matrix {
+            Ok(matrix) => {
+                info!(
+                    "Collision matrix loaded: {} pairs ({})",
+                    matrix.pairs.len(),
+                    path
+                );
+                matrix
+            }
+            Err(e) => {
+                error!("Loading collision matrix {}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// How layers `a` and `b` interact, `None` if they go through each other.
+    pub fn interaction(&self, a: PhysicsLayer, b: PhysicsLayer) -> Option<Interaction> {
+        self.pairs
+            .iter()
+            .find(|(first, second, _)| (*first, *second) == (a, b) || (*first, *second) == (b, a))
+            .map(|(_, _, interaction)| *interaction)
+    }
+
+    /// Layers that interact with `layer`, solid only or any way.
+    fn filter(&self, layer: PhysicsLayer, solid_only: bool) -> Group {
+        PhysicsLayer::ALL
+            .iter()
+            .filter(|other| match self.interaction(layer, **other) {
+                Some(Interaction::Solid) => true,
+                Some(Interaction::Overlap) => !solid_only,
+                None => false,
+            })
+            .fold(Group::NONE, |filter, other| filter | other.group())
+    }
+
+    /// Put `collider` on `layer`: contacts are only computed with the layers it interacts
+    /// with, and only solved with the solid ones.
+    pub fn apply(&self, layer: PhysicsLayer, collider: &mut Collider) {
+        collider.set_collision_groups(InteractionGroups::new(
+            layer.group(),
+            self.filter(layer, false),
+            InteractionTestMode::And,
+        ));
+        collider.set_solver_groups(InteractionGroups::new(
+            layer.group(),
+            self.filter(layer, true),
+            InteractionTestMode::And,
+        ));
+    }
+}
+
+/// A collider that reports overlaps and never pushes, for pickups and auras.
+/// It sees every kind of body, fixed walls included.
+pub fn sensor_collider(shape: ColliderBuilder) -> ColliderBuilder {
+    shape
+        .sensor(true)
+        .active_events(ActiveEvents::COLLISION_EVENTS)
+        .active_collision_types(ActiveCollisionTypes::all())
+}
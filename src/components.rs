@@ -1,6 +1,8 @@
 /// Note : Some components are located in a specific file for readability :
 /// Physics components are in `physic.rs`
+use hecs::{Entity, World};
 use macroquad::prelude::*;
+use macroquad::rand::{RandGenerator, RandomRange};
 
 use crate::asset_server::AssetId;
 
@@ -20,20 +22,31 @@ impl Default for Transform {
     }
 }
 
+/// Position of `entity`, the origin if it has no `Transform`.
+pub fn position_of(world: &World, entity: Entity) -> Vec2 {
+    world
+        .get::<&Transform>(entity)
+        .map(|transform| transform.position)
+        .unwrap_or_default()
+}
+
 pub struct Speed(pub f32);
 pub struct Health {
     pub actual: f32,
-    #[allow(dead_code)]
     pub max: f32,
 }
-#[allow(dead_code)]
+/// Damage dealt on contact.
 pub struct Damage(pub f32);
 
 // Drawing
 pub struct Sprite {
     pub asset_id: AssetId,
     pub scale: f32,
+    /// Part of the texture to draw, for spritesheets. The whole texture if `None`.
+    pub source: Option<Rect>,
 }
+/// Color multiplied with the `Sprite`, white when absent.
+pub struct Tint(pub Color);
 /// Text component to draw text.
 /// TODO: Make it more customizable, with [macroquad TextParams](https://docs.rs/macroquad/latest/macroquad/text/struct.TextParams.html)
 pub struct Text {
@@ -47,6 +60,31 @@ pub struct Enemy;
 /// Marker component for entities that should be despawned at the end of the frame.
 pub struct Despawn;
 
+/// Rank of the entity among the spawns of the run. Unlike the hecs `Entity`, it doesn't depend
+/// on entities outside the simulation, so it can go in the state hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpawnId(pub u64);
+
+/// Resource: the number of `SpawnId` given so far.
+#[derive(Default)]
+pub struct SpawnCounter(pub u64);
+
+/// Give a `SpawnId` to the entities spawned since the last call, in query order.
+/// Entities of the simulation all have a `Transform`, others are left out.
+pub fn spawn_id_system(world: &mut World, counter: &mut SpawnCounter) {
+    let new: Vec<Entity> = world
+        .query::<()>()
+        .with::<&Transform>()
+        .without::<&SpawnId>()
+        .iter()
+        .map(|(entity, ())| entity)
+        .collect();
+    for entity in new {
+        counter.0 += 1;
+        let _ = world.insert_one(entity, SpawnId(counter.0));
+    }
+}
+
 /// Resource to handle Fixed Update Logic (Tick)
 pub struct GameTick {
     pub tick_rate: f32,
@@ -63,3 +101,40 @@ impl Default for GameTick {
         }
     }
 }
+
+/// Resource: the only source of randomness of the gameplay.
+/// The same seed and the same inputs always give the same run.
+pub struct GameRng {
+    seed: u64,
+    generator: RandGenerator,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        let generator = RandGenerator::new();
+        generator.srand(seed);
+        Self { seed, generator }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Random value in `[low, high)`.
+    pub fn gen_range<T: RandomRange>(&mut self, low: T, high: T) -> T {
+        self.generator.gen_range(low, high)
+    }
+
+    /// Pick up to `amount` distinct items, in random order.
+    pub fn choose_multiple<T: Copy>(&mut self, items: &[T], amount: usize) -> Vec<T> {
+        let mut pool = items.to_vec();
+        let amount = amount.min(pool.len());
+        // Partial Fisher-Yates shuffle
+        for i in 0..amount {
+            let j = self.gen_range(i, pool.len());
+            pool.swap(i, j);
+        }
+        pool.truncate(amount);
+        pool
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+
+use crate::{
+    animation::Corpses,
+    components::{Damage, Despawn, Enemy, Health, Player, Sprite, Tint, Transform, position_of},
+    events::{DamageDealt, EnemyKilled, Events},
+    physic::CollideWith,
+};
+
+/// Per-source hit cooldowns: the same source can only hurt this entity once every `cooldown` seconds.
+pub struct HitCooldowns {
+    pub cooldown: f32,
+    pub timers: HashMap<Entity, f32>,
+}
+
+impl HitCooldowns {
+    pub fn new(cooldown: f32) -> Self {
+        Self {
+            cooldown,
+            timers: HashMap::new(),
+        }
+    }
+}
+
+/// Invulnerability frames: after a hit, the entity ignores every damage for `duration` seconds.
+pub struct Invulnerability {
+    pub duration: f32,
+    pub remaining: f32,
+}
+
+impl Invulnerability {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            remaining: 0.0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.remaining > 0.0
+    }
+}
+
+/// Apply `amount` damage from `source` to `target`, honoring hit cooldowns and invulnerability.
+/// Returns `true` if the damage was applied, and sends a `DamageDealt`.
+pub fn apply_damage(
+    world: &World,
+    events: &mut Events<DamageDealt>,
+    source: Entity,
+    target: Entity,
+    amount: f32,
+) -> bool {
+    let Ok(mut health) = world.get::<&mut Health>(target) else {
+        return false;
+    };
+
+    if let Ok(invulnerability) = world.get::<&Invulnerability>(target) {
+        if invulnerability.is_active() {
+            return false;
+        }
+    }
+
+    if let Ok(mut hit_cooldowns) = world.get::<&mut HitCooldowns>(target) {
+        if hit_cooldowns.timers.contains_key(&source) {
+            return false;
+        }
+        let cooldown = hit_cooldowns.cooldown;
+        hit_cooldowns.timers.insert(source, cooldown);
+    }
+
+    if let Ok(mut invulnerability) = world.get::<&mut Invulnerability>(target) {
+        invulnerability.remaining = invulnerability.duration;
+    }
+
+    health.actual = (health.actual - amount).max(0.0);
+    events.send(DamageDealt {
+        target,
+        amount,
+        position: position_of(world, target),
+    });
+    true
+}
+
+/// Tick hit cooldowns and invulnerability timers.
+pub fn damage_timers_system(world: &mut World, dt: f32) {
+    for (_id, hit_cooldowns) in world.query_mut::<&mut HitCooldowns>() {
+        hit_cooldowns.timers.retain(|_source, timer| {
+            *timer -= dt;
+            *timer > 0.0
+        });
+    }
+
+    for (_id, invulnerability) in world.query_mut::<&mut Invulnerability>() {
+        invulnerability.remaining = (invulnerability.remaining - dt).max(0.0);
+    }
+}
+
+/// Turn contacts between enemies and the player into health loss.
+pub fn contact_damage_system(world: &mut World, events: &mut Events<DamageDealt>) {
+    let mut hits = Vec::new();
+    for (player, collide_with) in world.query::<&CollideWith>().with::<&Player>().iter() {
+        for other in collide_with.0.iter() {
+            if !world.satisfies::<&Enemy>(*other).unwrap_or(false) {
+                continue;
+            }
+            if let Ok(damage) = world.get::<&Damage>(*other) {
+                hits.push((*other, player, damage.0));
+            }
+        }
+    }
+
+    for (source, target, amount) in hits {
+        if apply_damage(world, events, source, target, amount) {
+            log::debug!("{:?} hit {:?} for {} damage", source, target, amount);
+        }
+    }
+}
+
+/// Mark dead enemies for despawn, leaving a corpse to play their death when there are `corpses`.
+/// Returns the number of enemies killed.
+pub fn enemy_death_system(
+    world: &mut World,
+    events: &mut Events<EnemyKilled>,
+    mut corpses: Option<&mut Corpses>,
+) -> u32 {
+    let dead: Vec<Entity> = world
+        .query::<&Health>()
+        .with::<&Enemy>()
+        .without::<&Despawn>()
+        .iter()
+        .filter(|(_id, health)| health.actual <= 0.0)
+        .map(|(id, _health)| id)
+        .collect();
+
+    let killed = dead.len() as u32;
+    for id in dead {
+        events.send(EnemyKilled {
+            position: position_of(world, id),
+        });
+        if let Some(corpses) = corpses.as_deref_mut() {
+            if let Ok(mut query) = world.query_one::<(&Transform, &Sprite, Option<&Tint>)>(id) {
+                if let Some((transform, sprite, tint)) = query.get() {
+                    corpses.spawn(transform, sprite, tint);
+                }
+            }
+        }
+        let _ = world.insert_one(id, Despawn);
+    }
+    killed
+}
@@ -3,12 +3,30 @@ use std::collections::VecDeque;
 use hecs::World;
 use macroquad::prelude::*;
 
-use crate::{components::GameTick, physic::PhysicsResources};
+use crate::{
+    components::GameTick,
+    flow_field::{FLOW_CELL_SIZE, FlowField},
+    physic::PhysicsResources,
+};
 
 /// Un composant qui contiendra une liste de lignes à dessiner pour le débogage.
 /// On peut imaginer une seule entité "Debug" dans le monde qui possède ce composant.
 pub struct DebugLines(pub Vec<LineInfo>);
 
+/// Resource: the entities of the debug overlay, in a world of their own.
+/// The world of the run only holds the simulation, debug builds play the same runs as release ones.
+pub struct DebugWorld(pub World);
+
+impl DebugWorld {
+    pub fn new() -> Self {
+        let mut world = World::new();
+        // Entity for debug lines
+        world.spawn((DebugLines(Vec::new()),));
+        world.spawn((DebugData::new(),));
+        Self(world)
+    }
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct DebugData {
@@ -59,6 +77,20 @@ impl DebugLines {
     }
 }
 
+/// Show or hide the debug overlay.
+pub fn toggle_debug_display(world: &mut World) {
+    for (_id, debug_data) in world.query_mut::<&mut DebugData>() {
+        debug_data.display = !debug_data.display;
+    }
+}
+
+pub fn debug_display_enabled(world: &World) -> bool {
+    world
+        .query::<&DebugData>()
+        .iter()
+        .any(|(_id, debug_data)| debug_data.display)
+}
+
 /// Draw rapier collide box
 pub fn debug_draw_colliders_system(world: &mut World, physics: &PhysicsResources) {
     let debug_lines =
@@ -90,6 +122,31 @@ pub fn debug_draw_colliders_system(world: &mut World, physics: &PhysicsResources
     }
 }
 
+/// Draw an arrow per flow field cell, pointing the way enemies walk to the player.
+/// Nothing to draw without walls: enemies walk straight at the player.
+pub fn debug_draw_flow_field_system(world: &mut World, flow_field: &FlowField) {
+    if !flow_field.has_obstacles() {
+        return;
+    }
+    let debug_lines =
+        if let Some((_id, lines)) = world.query_mut::<&mut DebugLines>().into_iter().next() {
+            lines
+        } else {
+            return;
+        };
+
+    let length = FLOW_CELL_SIZE * 0.4;
+    for (center, direction) in flow_field.arrows() {
+        let tip = center + direction * length / 2.;
+        debug_lines.draw_line(tip - direction * length, tip, 1., SKYBLUE);
+        // Pointe de la flèche, deux traits à 150° du corps
+        for angle in [2.6_f32, -2.6] {
+            let head = Vec2::from_angle(angle).rotate(direction) * length / 3.;
+            debug_lines.draw_line(tip, tip + head, 1., SKYBLUE);
+        }
+    }
+}
+
 pub fn debug_infos_system(world: &mut World, game_tick: &GameTick) {
     for (_id, debug_data) in world.query_mut::<&mut DebugData>() {
         let frame_time = get_frame_time();
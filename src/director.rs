@@ -0,0 +1,311 @@
+use hecs::World;
+use log::{error, info, warn};
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    boss::spawn_boss,
+    components::{GameRng, Player},
+    enemy::{EnemyDefinition, spawn_enemy},
+    physic::{ColliderHandleComponent, PhysicsResources, collider_center},
+    simulation::GameData,
+};
+
+/// File describing the waves of a run.
+pub const TIMELINE_PATH: &str = "assets/timeline.ron";
+
+/// Distance kept between the camera view and the spawn ring, so enemies appear off-screen.
+const SPAWN_MARGIN: f32 = 64.0;
+/// Space between two enemies of a line formation.
+const LINE_SPACING: f32 = 40.0;
+
+/// How the enemies of a single spawn are placed on the spawn ring.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum Formation {
+    /// A single enemy, at a random point of the ring.
+    Scattered,
+    /// A pack of enemies, packed around a random point of the ring.
+    Swarm { count: u32, radius: f32 },
+    /// Enemies evenly spread on the whole ring, surrounding the player.
+    Ring { count: u32 },
+    /// A wall of enemies facing the player, at a random point of the ring.
+    Line { count: u32 },
+}
+
+/// A time-indexed wave: while it lasts, spawns formations of a weighted enemy mix.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wave {
+    /// Start time of the wave, in seconds since the start of the run.
+    pub start: f32,
+    /// End time of the wave, in seconds.
+    pub end: f32,
+    /// Enemy definitions name, with their weight in the mix.
+    pub enemies: Vec<(String, f32)>,
+    /// Formations spawned per second, as `(time since wave start, rate)` keys.
+    /// The rate is interpolated linearly between keys.
+    pub spawn_rate: Vec<(f32, f32)>,
+    pub formation: Formation,
+}
+
+impl Wave {
+    /// Formations per second, `time` seconds after the start of the wave.
+    pub fn rate_at(&self, time: f32) -> f32 {
+        let Some(first) = self.spawn_rate.first() else {
+            return 0.0;
+        };
+        if time <= first.0 {
+            return first.1;
+        }
+
+        for keys in self.spawn_rate.windows(2) {
+            let ((t0, r0), (t1, r1)) = (keys[0], keys[1]);
+            if time <= t1 {
+                let alpha = if t1 > t0 {
+                    (time - t0) / (t1 - t0)
+                } else {
+                    1.0
+                };
+                return r0 + (r1 - r0) * alpha;
+            }
+        }
+
+        self.spawn_rate.last().map(|key| key.1).unwrap_or(0.0)
+    }
+
+    /// Pick an enemy name from the weighted mix.
+    fn pick_enemy(&self, rng: &mut GameRng) -> Option<&str> {
+        let total: f32 = self.enemies.iter().map(|(_name, weight)| weight).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let mut roll = rng.gen_range(0.0, total);
+        for (name, weight) in self.enemies.iter() {
+            if roll < *weight {
+                return Some(name);
+            }
+            roll -= weight;
+        }
+        self.enemies.last().map(|(name, _weight)| name.as_str())
+    }
+}
+
+/// A one-shot event at a given time of the run.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimelineEvent {
+    /// Time of the event, in seconds since the start of the run.
+    pub time: f32,
+    pub enemy: String,
+    pub formation: Formation,
+}
+
+/// A boss entering the run, at a random point of the spawn ring.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BossSpawn {
+    /// Time of the spawn, in seconds since the start of the run.
+    pub time: f32,
+    /// Enemy definition of the boss, with its `boss` section.
+    pub enemy: String,
+    /// Multiplies the health of the definition.
+    #[serde(default = "default_health_multiplier")]
+    pub health_multiplier: f32,
+}
+
+fn default_health_multiplier() -> f32 {
+    1.0
+}
+
+/// The whole run, loaded from `assets/timeline.ron`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Timeline {
+    pub waves: Vec<Wave>,
+    #[serde(default)]
+    pub events: Vec<TimelineEvent>,
+    #[serde(default)]
+    pub bosses: Vec<BossSpawn>,
+}
+
+impl Timeline {
+    /// Load the timeline. An invalid file is logged and gives an empty timeline.
+    pub fn load(path: &str) -> Self {
+        let timeline = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| ron::from_str::<Timeline>(&content).map_err(|e| e.to_string()));
+
+        match timeline {
+            Ok(mut timeline) => {
+                timeline.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+                timeline.bosses.sort_by(|a, b| a.time.total_cmp(&b.time));
+                info!(
+                    "Timeline loaded: {} waves, {} events, {} bosses ({})",
+                    timeline.waves.len(),
+                    timeline.events.len(),
+                    timeline.bosses.len(),
+                    path
+                );
+                timeline
+            }
+            Err(e) => {
+                error!("Loading timeline {}: {}", path, e);
+                Timeline::default()
+            }
+        }
+    }
+}
+
+/// Play the `Timeline` of the run, on the fixed `GameTick`.
+#[derive(Default)]
+pub struct Director {
+    /// Time since the start of the run, in seconds.
+    pub elapsed: f32,
+    /// Fractional formations waiting to be spawned, per wave.
+    spawn_accumulators: Vec<f32>,
+    /// Index of the next `TimelineEvent` to trigger.
+    next_event: usize,
+    /// Index of the next `BossSpawn` to trigger.
+    next_boss: usize,
+}
+
+/// Get a random point on a circle of `radius` around `center`.
+fn random_point_on_ring(rng: &mut GameRng, center: Vec2, radius: f32) -> Vec2 {
+    center + Vec2::from_angle(rng.gen_range(0.0, std::f32::consts::TAU)) * radius
+}
+
+/// Get the center of every enemy of a formation.
+fn formation_positions(
+    rng: &mut GameRng,
+    formation: Formation,
+    player: Vec2,
+    radius: f32,
+) -> Vec<Vec2> {
+    match formation {
+        Formation::Scattered => vec![random_point_on_ring(rng, player, radius)],
+        Formation::Swarm {
+            count,
+            radius: swarm_radius,
+        } => {
+            let center = random_point_on_ring(rng, player, radius + swarm_radius);
+            (0..count)
+                .map(|_| {
+                    let distance = rng.gen_range(0.0, swarm_radius);
+                    random_point_on_ring(rng, center, distance)
+                })
+                .collect()
+        }
+        Formation::Ring { count } => {
+            let offset = rng.gen_range(0.0, std::f32::consts::TAU);
+            (0..count)
+                .map(|i| {
+                    let angle = offset + std::f32::consts::TAU * i as f32 / count as f32;
+                    player + Vec2::from_angle(angle) * radius
+                })
+                .collect()
+        }
+        Formation::Line { count } => {
+            let center = random_point_on_ring(rng, player, radius);
+            let along = (center - player).normalize_or_zero().perp();
+            let first = -LINE_SPACING * (count.max(1) - 1) as f32 / 2.0;
+            (0..count)
+                .map(|i| center + along * (first + LINE_SPACING * i as f32))
+                .collect()
+        }
+    }
+}
+
+/// Spawn `formation` of enemies from `definition`, around the player.
+fn spawn_formation(
+    world: &mut World,
+    rng: &mut GameRng,
+    definition: &EnemyDefinition,
+    formation: Formation,
+    player: Vec2,
+    radius: f32,
+) {
+    for center in formation_positions(rng, formation, player, radius) {
+        spawn_enemy(world, rng, definition, center);
+    }
+}
+
+/// Advance the timeline by one tick, and spawn the enemies it asks for.
+/// `view_half_extents` is half the size of the camera view, enemies spawn just outside of it.
+pub fn director_system(
+    world: &mut World,
+    physics: &PhysicsResources,
+    director: &mut Director,
+    rng: &mut GameRng,
+    data: &GameData,
+    view_half_extents: Vec2,
+    dt: f32,
+) {
+    let (timeline, registry) = (&data.timeline, &data.enemies);
+    director.elapsed += dt;
+    let time = director.elapsed;
+
+    let Some(player) = world
+        .query::<&ColliderHandleComponent>()
+        .with::<&Player>()
+        .iter()
+        .find_map(|(_id, handle)| collider_center(physics, handle))
+    else {
+        return;
+    };
+    // Circle around the camera view, so no corner of the screen is inside the ring.
+    let radius = view_half_extents.length() + SPAWN_MARGIN;
+
+    director
+        .spawn_accumulators
+        .resize(timeline.waves.len(), 0.0);
+
+    for (wave, accumulator) in timeline
+        .waves
+        .iter()
+        .zip(director.spawn_accumulators.iter_mut())
+    {
+        if time < wave.start || time >= wave.end {
+            continue;
+        }
+
+        *accumulator += wave.rate_at(time - wave.start) * dt;
+        while *accumulator >= 1.0 {
+            *accumulator -= 1.0;
+
+            let Some(name) = wave.pick_enemy(rng) else {
+                break;
+            };
+            match registry.get(name) {
+                Some(definition) => {
+                    spawn_formation(world, rng, definition, wave.formation, player, radius);
+                }
+                None => warn!("Wave enemy {} has no definition", name),
+            }
+        }
+    }
+
+    while let Some(event) = timeline.events.get(director.next_event) {
+        if event.time > time {
+            break;
+        }
+        director.next_event += 1;
+
+        let Some(definition) = registry.get(&event.enemy) else {
+            warn!("Timeline event enemy {} has no definition", event.enemy);
+            continue;
+        };
+        spawn_formation(world, rng, definition, event.formation, player, radius);
+    }
+
+    while let Some(spawn) = timeline.bosses.get(director.next_boss) {
+        if spawn.time > time {
+            break;
+        }
+        director.next_boss += 1;
+
+        let Some(definition) = registry.get(&spawn.enemy) else {
+            warn!("Boss {} has no definition", spawn.enemy);
+            continue;
+        };
+        let center = random_point_on_ring(rng, player, radius);
+        spawn_boss(world, rng, definition, center, spawn.health_multiplier);
+        info!("Boss {} spawned at {:.0}s", spawn.enemy, time);
+    }
+}
@@ -1,82 +1,545 @@
-use hecs::World;
+use std::f32::consts::TAU;
+
+use hecs::{Entity, EntityBuilder, World};
+use log::{error, info, warn};
 use macroquad::prelude::*;
 use rapier2d::prelude::*;
+use serde::Deserialize;
 
 use crate::{
-    asset_server::{self},
-    components::{Damage, Enemy, Player, Speed, Sprite, Transform},
-    physic::{PhysicsResources, RigidBodyHandleComponent},
+    animation::Animator,
+    asset_server::AssetServer,
+    boss::BossDefinition,
+    collision::PhysicsLayer,
+    components::{Damage, Despawn, Enemy, GameRng, Health, Player, Speed, Sprite, Tint, Transform},
+    flow_field::FlowField,
+    physic::{
+        ColliderHandleComponent, PhysicsResources, RigidBodyHandleComponent, collider_center,
+    },
+    progression::XpReward,
+    spatial::SpatialIndex,
+    steering::{Steering, obstacle_filter},
+    weapon::{Projectile, spawn_projectile},
 };
 
-pub struct EnemySpawner {
+/// Directory scanned for enemy archetypes.
+pub const ENEMY_DEFINITIONS_DIR: &str = "assets/enemies";
+
+/// Seconds before an enemy projectile disappears.
+const ENEMY_PROJECTILE_LIFETIME: f32 = 4.0;
+/// Enemy projectiles are tinted, to tell them from the player ones.
+const ENEMY_PROJECTILE_TINT: Color = VIOLET;
+/// Shooters fire once the player is closer than their `distance` times this.
+const SHOOTER_RANGE_FACTOR: f32 = 1.25;
+
+/// How an enemy moves and attacks, with its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum AiBehaviour {
+    /// Walk at the player.
+    Chase,
+    /// Walk at the player. Once in `range`, stand still for `telegraph` seconds, then dash at
+    /// `dash_speed` for `dash_duration` seconds and rest for `recover` seconds.
+    Charger {
+        range: f32,
+        telegraph: f32,
+        dash_speed: f32,
+        dash_duration: f32,
+        recover: f32,
+    },
+    /// Keep `distance` pixels away from the player, and fire at it every `fire_interval`
+    /// seconds.
+    Shooter {
+        distance: f32,
+        fire_interval: f32,
+        projectile_speed: f32,
+        projectile_damage: f32,
+    },
+    /// Circle the player, `radius` pixels away.
+    Orbiter { radius: f32, clockwise: bool },
+    /// Walk at the player in a sine wave, turning up to `amplitude` radians aside,
+    /// `frequency` times per second.
+    Swarm { amplitude: f32, frequency: f32 },
+}
+
+/// Where a `Charger` is in its attack.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChargePhase {
+    #[default]
+    Approach,
+    /// Standing still, about to dash `length` pixels along `direction`.
+    Telegraph {
+        direction: Vec2,
+        length: f32,
+    },
+    Dash {
+        direction: Vec2,
+    },
+    Recover,
+}
+
+/// What the `AiBehaviour` of an enemy is doing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AiState {
+    /// Seconds since the enemy spawned.
+    pub age: f32,
+    /// Seconds left in the charge phase, or before the next shot.
     pub timer: f32,
-    pub spawn_interval: f32,
+    pub charge: ChargePhase,
+    /// Angle in `[0, TAU)`, rolled at spawn, so enemies of the same kind don't move in step
+    /// and stacked ones split apart.
+    pub phase: f32,
 }
 
-impl Default for EnemySpawner {
-    fn default() -> Self {
-        Self {
-            timer: 0.0,
-            spawn_interval: 0.5,
+/// How an enemy wants to move this tick.
+enum Move {
+    /// Walk along a heading, steered through the crowd and around walls.
+    Steer(Vec2),
+    /// Move at this exact velocity, for dashes and standing still.
+    Exact(Vec2),
+}
+
+/// A projectile an enemy fires.
+struct Shot {
+    velocity: Vec2,
+    damage: f32,
+}
+
+impl AiBehaviour {
+    /// Advance `state` by `dt` seconds and choose how the enemy moves, and whether it fires.
+    /// `to_player` goes from the enemy to the player, `chase` is the heading to walk to the
+    /// player around walls.
+    fn update(
+        &self,
+        state: &mut AiState,
+        to_player: Vec2,
+        chase: Vec2,
+        dt: f32,
+    ) -> (Move, Option<Shot>) {
+        state.age += dt;
+        state.timer = (state.timer - dt).max(0.0);
+
+        match *self {
+            AiBehaviour::Chase => (Move::Steer(chase), None),
+            AiBehaviour::Charger {
+                range,
+                telegraph,
+                dash_speed,
+                dash_duration,
+                recover,
+            } => {
+                let movement = match state.charge {
+                    ChargePhase::Approach if to_player.length() <= range => {
+                        state.charge = ChargePhase::Telegraph {
+                            direction: to_player.normalize_or_zero(),
+                            length: dash_speed * dash_duration,
+                        };
+                        state.timer = telegraph;
+                        Move::Exact(Vec2::ZERO)
+                    }
+                    ChargePhase::Approach => Move::Steer(chase),
+                    ChargePhase::Telegraph { direction, .. } => {
+                        if state.timer <= 0.0 {
+                            state.charge = ChargePhase::Dash { direction };
+                            state.timer = dash_duration;
+                            Move::Exact(direction * dash_speed)
+                        } else {
+                            Move::Exact(Vec2::ZERO)
+                        }
+                    }
+                    ChargePhase::Dash { direction } => {
+                        if state.timer <= 0.0 {
+                            state.charge = ChargePhase::Recover;
+                            state.timer = recover;
+                            Move::Exact(Vec2::ZERO)
+                        } else {
+                            Move::Exact(direction * dash_speed)
+                        }
+                    }
+                    ChargePhase::Recover => {
+                        if state.timer <= 0.0 {
+                            state.charge = ChargePhase::Approach;
+                        }
+                        Move::Exact(Vec2::ZERO)
+                    }
+                };
+                (movement, None)
+            }
+            AiBehaviour::Shooter {
+                distance,
+                fire_interval,
+                projectile_speed,
+                projectile_damage,
+            } => {
+                let range = to_player.length();
+                let toward = to_player.normalize_or_zero();
+
+                let shot = if state.timer <= 0.0 && range <= distance * SHOOTER_RANGE_FACTOR {
+                    state.timer = fire_interval;
+                    Some(Shot {
+                        velocity: toward * projectile_speed,
+                        damage: projectile_damage,
+                    })
+                } else {
+                    None
+                };
+
+                // Walk in when too far, back off when too close, slowing down near `distance`
+                let offset = ((range - distance) / (distance * 0.25)).clamp(-1.0, 1.0);
+                let heading = if offset > 0.0 {
+                    chase * offset
+                } else {
+                    toward * offset
+                };
+                (Move::Steer(heading), shot)
+            }
+            AiBehaviour::Orbiter { radius, clockwise } => {
+                let range = to_player.length();
+                if range > radius * 2.0 {
+                    return (Move::Steer(chase), None);
+                }
+
+                let toward = to_player.normalize_or_zero();
+                // `perp` turns counterclockwise on screen, the y axis points down
+                let around = if clockwise {
+                    -toward.perp()
+                } else {
+                    toward.perp()
+                };
+                // Back onto the circle, then along it
+                let back = toward * ((range - radius) / radius).clamp(-1.0, 1.0);
+                (Move::Steer((around + back).normalize_or_zero()), None)
+            }
+            AiBehaviour::Swarm {
+                amplitude,
+                frequency,
+            } => {
+                // Enemies of a swarm don't wave in step
+                let angle = amplitude * (TAU * frequency * state.age + state.phase).sin();
+                (Move::Steer(Vec2::from_angle(angle).rotate(chase)), None)
+            }
         }
     }
 }
 
-pub fn enemy_spawner_system(world: &mut World, spawner: &mut EnemySpawner) {
-    spawner.timer += get_frame_time();
+/// Enemies an enemy breaks into when killed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Split {
+    /// Definition name of the smaller enemies.
+    pub enemy: String,
+    pub count: u32,
+}
 
-    if spawner.timer >= spawner.spawn_interval {
-        spawner.timer = 0.0;
+/// An enemy archetype, loaded from a `.ron` file in `assets/enemies`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnemyDefinition {
+    pub name: String,
+    pub health: f32,
+    pub speed: f32,
+    /// Damage dealt to the player on contact.
+    pub damage: f32,
+    /// Width and height of the collision box, centered on the sprite.
+    pub collider_size: (f32, f32),
+    /// Path of the texture, loaded by the `AssetServer`.
+    pub sprite: String,
+    /// Width and height of the texture, in pixels.
+    pub sprite_size: (f32, f32),
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Experience dropped when killed.
+    pub xp: u32,
+    pub behaviour: AiBehaviour,
+    /// How the enemy moves in a crowd.
+    #[serde(default)]
+    pub steering: Steering,
+    /// Enemies spawned where it dies.
+    #[serde(default)]
+    pub split: Option<Split>,
+    /// Color multiplied with the sprite, as RGB from 0 to 1.
+    #[serde(default)]
+    pub tint: Option<(f32, f32, f32)>,
+    /// Attack phases, when spawned as a boss by the timeline.
+    #[serde(default)]
+    pub boss: Option<BossDefinition>,
+}
 
-        // Apparaît à une position fixe pour l'exemple.
-        let spawn_position = vec2(200.0, 200.0);
+fn default_scale() -> f32 {
+    1.0
+}
 
-        // Apparaît à une position fixe pour l'exemple.
-        let spawn_position = vec2(200.0, 200.0);
+/// Every enemy archetype known to the game.
+#[derive(Default)]
+pub struct EnemyRegistry {
+    pub definitions: Vec<EnemyDefinition>,
+}
 
-        let enemy_body = RigidBodyBuilder::dynamic()
-            .translation([spawn_position.x, spawn_position.y].into())
-            .lock_rotations()
-            .build();
-        let enemy_collider = ColliderBuilder::cuboid(16., 16.)
-            .translation([32. / 2., 32. / 2.].into())
-            .active_events(ActiveEvents::COLLISION_EVENTS)
-            .build();
+impl EnemyRegistry {
+    /// Load every `.ron` file in `dir`. Invalid files are logged and skipped.
+    pub fn load(dir: &str) -> Self {
+        let mut registry = Self::default();
+
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Reading enemy definitions in {}: {}", dir, e);
+                return registry;
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "ron"))
+            .collect();
+        // Keep a stable order, read_dir doesn't guarantee one.
+        paths.sort();
+
+        for path in paths {
+            let definition = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    ron::from_str::<EnemyDefinition>(&content).map_err(|e| e.to_string())
+                });
+
+            match definition {
+                Ok(definition) => {
+                    info!(
+                        "Enemy definition loaded: {} ({})",
+                        definition.name,
+                        path.display()
+                    );
+                    registry.definitions.push(definition);
+                }
+                Err(e) => error!("Loading enemy definition {}: {}", path.display(), e),
+            }
+        }
 
-        world.spawn((
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EnemyDefinition> {
+        self.definitions
+            .iter()
+            .find(|definition| definition.name == name)
+    }
+
+    /// Textures used by the definitions, to load with the `AssetServer`.
+    pub fn sprite_paths(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self
+            .definitions
+            .iter()
+            .map(|definition| definition.sprite.as_str())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+/// Spawn an enemy from its archetype, centered on `center`.
+pub fn spawn_enemy(
+    world: &mut World,
+    rng: &mut GameRng,
+    definition: &EnemyDefinition,
+    center: Vec2,
+) -> Entity {
+    let size = vec2(definition.sprite_size.0, definition.sprite_size.1) * definition.scale;
+    // Bodies are positioned by the top-left of their sprite.
+    let position = center - size / 2.;
+
+    let enemy_body = RigidBodyBuilder::dynamic()
+        .translation([position.x, position.y].into())
+        .lock_rotations()
+        .build();
+    let enemy_collider = ColliderBuilder::cuboid(
+        definition.collider_size.0 / 2.,
+        definition.collider_size.1 / 2.,
+    )
+    .translation([size.x / 2., size.y / 2.].into())
+    .active_events(ActiveEvents::COLLISION_EVENTS)
+    .build();
+
+    // Too many components for a single tuple, add them in bundles
+    let mut enemy = EntityBuilder::new();
+    enemy
+        .add_bundle((
             Enemy,
             Transform {
-                position: spawn_position,
+                position,
                 ..Default::default()
             },
-            Speed(80.0),
-            Damage(10.0),
             Sprite {
-                asset_id: asset_server::assets::enemy(),
-                scale: 1.0,
+                asset_id: AssetServer::compute_id(&definition.sprite),
+                scale: definition.scale,
+                source: None,
+            },
+            Animator::new(Some("idle")),
+        ))
+        .add_bundle((
+            Speed(definition.speed),
+            Damage(definition.damage),
+            Health {
+                actual: definition.health,
+                max: definition.health,
+            },
+            XpReward(definition.xp),
+            definition.behaviour,
+            AiState {
+                phase: rng.gen_range(0.0, TAU),
+                ..Default::default()
             },
-            enemy_body,
-            enemy_collider,
-        ));
+            definition.steering,
+        ))
+        .add_bundle((PhysicsLayer::Enemy, enemy_body, enemy_collider));
+    if let Some(split) = &definition.split {
+        enemy.add(split.clone());
     }
+    if let Some((r, g, b)) = definition.tint {
+        enemy.add(Tint(Color::new(r, g, b, 1.0)));
+    }
+    world.spawn(enemy.build())
 }
 
-pub fn enemy_ai_system(world: &mut World, physics: &mut PhysicsResources) {
-    let mut player_pos = None;
-    for (_id, (transform,)) in world.query::<(&Transform,)>().with::<&Player>().iter() {
-        player_pos = Some(transform.position);
-    }
+/// Move every enemy as its behaviour says, around the others and the walls, and fire the
+/// enemy projectiles.
+pub fn enemy_ai_system(
+    world: &mut World,
+    physics: &mut PhysicsResources,
+    index: &SpatialIndex,
+    flow: &FlowField,
+    dt: f32,
+) {
+    let Some(player_center) = world
+        .query::<&ColliderHandleComponent>()
+        .with::<&Player>()
+        .iter()
+        .find_map(|(_id, handle)| collider_center(physics, handle))
+    else {
+        return;
+    };
 
-    if let Some(player_pos) = player_pos {
-        for (_id, (transform, speed, rb_handle)) in world
-            .query_mut::<(&Transform, &Speed, &RigidBodyHandleComponent)>()
+    // Ray casts borrow the physics world, velocities are set afterwards
+    let mut velocities = Vec::new();
+    let mut shots = Vec::new();
+    {
+        let obstacles = physics.broad_phase.as_query_pipeline(
+            physics.narrow_phase.query_dispatcher(),
+            &physics.rigid_body_set,
+            &physics.collider_set,
+            obstacle_filter(),
+        );
+        for (entity, (collider_handle, speed, behaviour, state, steering, body_handle)) in world
+            .query::<(
+                &ColliderHandleComponent,
+                &Speed,
+                &AiBehaviour,
+                &mut AiState,
+                &Steering,
+                &RigidBodyHandleComponent,
+            )>()
             .with::<&Enemy>()
+            .iter()
         {
-            let direction = (player_pos - transform.position).normalize_or_zero();
-            let desired_velocity = direction * speed.0;
-            if let Some(body) = physics.rigid_body_set.get_mut(rb_handle.0) {
-                body.set_linvel([desired_velocity.x, desired_velocity.y].into(), true);
+            let Some(position) = collider_center(physics, collider_handle) else {
+                continue;
+            };
+            let to_player = player_center - position;
+            let chase = flow
+                .direction_at(position)
+                .unwrap_or_else(|| to_player.normalize_or_zero());
+
+            let (movement, shot) = behaviour.update(state, to_player, chase, dt);
+            if let Some(shot) = shot {
+                shots.push((position, shot));
             }
+            let velocity = match movement {
+                Move::Steer(seek) => {
+                    steering.direction(
+                        entity,
+                        state.phase,
+                        position,
+                        seek,
+                        &index.enemies,
+                        &obstacles,
+                    ) * speed.0
+                }
+                Move::Exact(velocity) => velocity,
+            };
+            velocities.push((body_handle.0, velocity));
         }
     }
+
+    for (body_handle, velocity) in velocities {
+        if let Some(body) = physics.rigid_body_set.get_mut(body_handle) {
+            body.set_linvel([velocity.x, velocity.y].into(), true);
+        }
+    }
+
+    for (origin, shot) in shots {
+        spawn_enemy_projectile(world, origin, shot.velocity, shot.damage);
+    }
+}
+
+/// Fire an enemy projectile from `origin`, hurting the player only.
+pub fn spawn_enemy_projectile(world: &mut World, origin: Vec2, velocity: Vec2, damage: f32) {
+    let projectile = spawn_projectile(
+        world,
+        origin,
+        velocity,
+        Projectile {
+            damage,
+            pierce: 0,
+            lifetime: ENEMY_PROJECTILE_LIFETIME,
+            hits: Vec::new(),
+        },
+        PhysicsLayer::EnemyProjectile,
+    );
+    let _ = world.insert_one(projectile, Tint(ENEMY_PROJECTILE_TINT));
+}
+
+/// Spawn `count` enemies from `definition`, evenly spread on a circle of `radius` around
+/// `center`.
+pub fn spawn_around(
+    world: &mut World,
+    rng: &mut GameRng,
+    definition: &EnemyDefinition,
+    center: Vec2,
+    count: u32,
+    radius: f32,
+) {
+    for i in 0..count {
+        let angle = TAU * i as f32 / count as f32;
+        spawn_enemy(
+            world,
+            rng,
+            definition,
+            center + Vec2::from_angle(angle) * radius,
+        );
+    }
+}
+
+/// Break the splitters killed this tick into smaller enemies.
+pub fn split_system(
+    world: &mut World,
+    rng: &mut GameRng,
+    physics: &PhysicsResources,
+    registry: &EnemyRegistry,
+) {
+    let splits: Vec<(Vec2, Split)> = world
+        .query::<(&Health, &Split, &ColliderHandleComponent)>()
+        .with::<(&Enemy, &Despawn)>()
+        .iter()
+        .filter(|(_id, (health, _split, _handle))| health.actual <= 0.0)
+        .filter_map(|(_id, (_health, split, handle))| {
+            collider_center(physics, handle).map(|center| (center, split.clone()))
+        })
+        .collect();
+
+    for (center, split) in splits {
+        let Some(definition) = registry.get(&split.enemy) else {
+            warn!("Split enemy {} has no definition", split.enemy);
+            continue;
+        };
+        // Around the dead enemy, a body apart
+        let spread = definition.collider_size.0.max(definition.collider_size.1);
+        spawn_around(world, rng, definition, center, split.count, spread);
+    }
 }
@@ -0,0 +1,185 @@
+//! Typed gameplay events, sent by the gameplay and read by the presentation.
+//!
+//! Each event type has its own `Events<T>` resource, added with `add_event`. It keeps two
+//! queues: the events of the current frame and of the previous one, swapped by `update_events`
+//! at the start of every frame, before its ticks.
+//!
+//! Swapping per frame and not per tick is what the readers need: they run once per frame, after
+//! the simulation. Whether the frame ran no tick or several, a reader sees every event sent
+//! since its last read, and its `EventCursor` makes sure it sees each one once. Gameplay systems
+//! only send events, the simulation never depends on them.
+//!
+//! Every run has its own events: readers outliving a run start over with a new cursor.
+use hecs::Entity;
+use macroquad::prelude::*;
+
+use crate::schedule::Resources;
+
+/// Where a reader is in an `Events<T>`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EventCursor {
+    /// Id of the next event to read.
+    next: usize,
+}
+
+pub struct Events<T> {
+    /// Sent during the previous frame.
+    previous: Vec<T>,
+    /// Sent during the current frame.
+    current: Vec<T>,
+    /// Id of the first event of `previous`, ids count every event ever sent.
+    start: usize,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            start: 0,
+        }
+    }
+}
+
+impl<T> Events<T> {
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    /// Drop the events of the previous frame. Call once at the start of each frame.
+    pub fn update(&mut self) {
+        self.start += self.previous.len();
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    /// Number of events sent so far.
+    fn sent(&self) -> usize {
+        self.start + self.previous.len() + self.current.len()
+    }
+
+    /// Events not read yet through `cursor`, oldest first.
+    pub fn read<'a>(&'a self, cursor: &mut EventCursor) -> impl Iterator<Item = &'a T> + 'a {
+        let next = cursor.next;
+        if next < self.start {
+            log::warn!(
+                "Missed {} {} events",
+                self.start - next,
+                std::any::type_name::<T>()
+            );
+        }
+        cursor.next = self.sent();
+        self.previous
+            .iter()
+            .chain(self.current.iter())
+            .skip(next.saturating_sub(self.start))
+    }
+}
+
+/// A hit that took health.
+#[derive(Debug, Clone, Copy)]
+pub struct DamageDealt {
+    pub target: Entity,
+    pub amount: f32,
+    /// Where the target was hit.
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EnemyKilled {
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerLeveledUp;
+
+/// What the player can pick up.
+#[derive(Debug, Clone, Copy)]
+pub enum Item {
+    XpGem {
+        value: u32,
+    },
+    /// Dropped by bosses.
+    Treasure {
+        upgrades: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ItemPicked {
+    pub item: Item,
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerDied {
+    pub position: Vec2,
+}
+
+/// Resource: how to swap each `Events<T>` added with `add_event`.
+#[derive(Default)]
+struct EventQueues(Vec<fn(&Resources)>);
+
+/// Add the `Events<T>` resource, swapped with the others by `update_events`.
+pub fn add_event<T: 'static>(resources: &mut Resources) {
+    if !resources.contains::<EventQueues>() {
+        resources.insert(EventQueues::default());
+    }
+    resources
+        .get_mut::<EventQueues>()
+        .0
+        .push(|resources| resources.get_mut::<Events<T>>().update());
+    resources.insert(Events::<T>::default());
+}
+
+/// Swap the queues of every event type. Call once at the start of each frame.
+pub fn update_events(resources: &Resources) {
+    if let Some(queues) = resources.try_get::<EventQueues>() {
+        for update in queues.0.iter() {
+            update(resources);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_readers_see_every_event_once() {
+        let mut resources = Resources::default();
+        add_event::<EnemyKilled>(&mut resources);
+        let mut cursor = EventCursor::default();
+        let read = |cursor: &mut EventCursor| {
+            resources
+                .get::<Events<EnemyKilled>>()
+                .read(cursor)
+                .map(|killed| killed.position.x)
+                .collect::<Vec<_>>()
+        };
+
+        // A frame of three ticks
+        update_events(&resources);
+        for x in [1.0, 2.0, 3.0] {
+            resources
+                .get_mut::<Events<EnemyKilled>>()
+                .send(EnemyKilled {
+                    position: vec2(x, 0.0),
+                });
+        }
+        assert_eq!(read(&mut cursor), [1.0, 2.0, 3.0]);
+
+        // A frame without ticks
+        update_events(&resources);
+        assert!(read(&mut cursor).is_empty());
+
+        // Events sent before a frame without read are still there on the next one
+        resources
+            .get_mut::<Events<EnemyKilled>>()
+            .send(EnemyKilled {
+                position: vec2(4.0, 0.0),
+            });
+        update_events(&resources);
+        assert_eq!(read(&mut cursor), [4.0]);
+    }
+}
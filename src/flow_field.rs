@@ -0,0 +1,483 @@
+//! Flow field to the player, so hordes walk around walls instead of into them.
+//!
+//! The field covers a square window of cells centered on the player. Each cell points to its
+//! neighbour closest to the player, by walking distance around the blocked cells.
+//! Enemies read the cell they stand in, whatever their number.
+//!
+//! The window moves once the player gets near its edge. Cells are sampled when they enter the
+//! window or a wall over them is added or removed, distances when the player changes cell.
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use hecs::World;
+use macroquad::prelude::*;
+use rapier2d::prelude::*;
+
+use crate::{
+    collision::PhysicsLayer,
+    components::Player,
+    physic::{ColliderHandleComponent, PhysicsResources, collider_center},
+};
+
+/// Side of a cell, in pixels. Enemies are about this size.
+pub const FLOW_CELL_SIZE: f32 = 32.0;
+/// Cells per side of the window.
+const WINDOW_SIZE: i32 = 64;
+/// The window moves when the player is this many cells from its center.
+const RECENTER_DISTANCE: i32 = WINDOW_SIZE / 4;
+
+/// Cost of a straight step. Diagonal steps cost about `STEP * sqrt(2)`.
+const STEP: u32 = 10;
+const DIAGONAL_STEP: u32 = 14;
+
+type Cell = (i32, i32);
+/// Lowest and highest cells of a rectangle of cells, both included.
+type Area = (Cell, Cell);
+
+/// Neighbour offsets, straight ones first.
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+pub struct FlowField {
+    /// World cell of the top-left corner of the window.
+    origin: Cell,
+    /// Cell of the player, the goal of the field.
+    goal: Option<Cell>,
+    blocked: Vec<bool>,
+    /// Walking distance to the goal, `u32::MAX` where unreachable.
+    costs: Vec<u32>,
+    /// Unit direction to walk in, zero on the goal and where unreachable.
+    directions: Vec<Vec2>,
+    /// Colliders the blocked cells were sampled from.
+    walls: Vec<ColliderHandle>,
+    /// Cells under the bounding box of each wall.
+    wall_areas: HashMap<ColliderHandle, Area>,
+    /// A wall is in the window. Without one, walking straight at the player is shorter.
+    has_obstacles: bool,
+}
+
+impl Default for FlowField {
+    fn default() -> Self {
+        let cells = (WINDOW_SIZE * WINDOW_SIZE) as usize;
+        Self {
+            origin: (0, 0),
+            goal: None,
+            blocked: vec![false; cells],
+            costs: vec![u32::MAX; cells],
+            directions: vec![Vec2::ZERO; cells],
+            walls: Vec::new(),
+            wall_areas: HashMap::new(),
+            has_obstacles: false,
+        }
+    }
+}
+
+fn cell_of(position: Vec2) -> Cell {
+    (
+        (position.x / FLOW_CELL_SIZE).floor() as i32,
+        (position.y / FLOW_CELL_SIZE).floor() as i32,
+    )
+}
+
+fn cell_center(cell: Cell) -> Vec2 {
+    (vec2(cell.0 as f32, cell.1 as f32) + 0.5) * FLOW_CELL_SIZE
+}
+
+/// Index of a world cell in the window whose top-left corner is `origin`.
+fn window_index(origin: Cell, cell: Cell) -> Option<usize> {
+    let (x, y) = (cell.0 - origin.0, cell.1 - origin.1);
+    if (0..WINDOW_SIZE).contains(&x) && (0..WINDOW_SIZE).contains(&y) {
+        Some((y * WINDOW_SIZE + x) as usize)
+    } else {
+        None
+    }
+}
+
+/// Cells in both `a` and `b`, `None` if they don't overlap.
+fn intersection(a: Area, b: Area) -> Option<Area> {
+    let min = ((a.0).0.max((b.0).0), (a.0).1.max((b.0).1));
+    let max = ((a.1).0.min((b.1).0), (a.1).1.min((b.1).1));
+    (min.0 <= max.0 && min.1 <= max.1).then_some((min, max))
+}
+
+impl FlowField {
+    /// Index of a world cell in the window.
+    fn index(&self, cell: Cell) -> Option<usize> {
+        window_index(self.origin, cell)
+    }
+
+    fn cell_at(&self, index: usize) -> Cell {
+        let index = index as i32;
+        (
+            self.origin.0 + index % WINDOW_SIZE,
+            self.origin.1 + index / WINDOW_SIZE,
+        )
+    }
+
+    /// Direction to walk in from `position`, `None` where walking straight at the player works
+    /// as well: no wall around, outside the window, or on the player cell.
+    pub fn direction_at(&self, position: Vec2) -> Option<Vec2> {
+        if !self.has_obstacles {
+            return None;
+        }
+        let direction = self.directions[self.index(cell_of(position))?];
+        (direction != Vec2::ZERO).then_some(direction)
+    }
+
+    /// A wall is in the window, the field leads somewhere walking straight wouldn't.
+    pub fn has_obstacles(&self) -> bool {
+        self.has_obstacles
+    }
+
+    /// Follow the player at `target`, around the `walls` colliders.
+    /// Only what changed is sampled again: the cells entering the window as it moves, and the
+    /// cells of the walls added or removed. Distances are computed again when the player changes
+    /// cell, and only while a wall is in the window.
+    pub fn update(&mut self, target: Vec2, walls: Vec<ColliderHandle>, colliders: &ColliderSet) {
+        let goal = cell_of(target);
+        let center = (
+            self.origin.0 + WINDOW_SIZE / 2,
+            self.origin.1 + WINDOW_SIZE / 2,
+        );
+        let moved = (goal.0 - center.0).abs().max((goal.1 - center.1).abs());
+        let recenter = self.goal.is_none() || moved >= RECENTER_DISTANCE;
+        if !recenter && self.goal == Some(goal) && self.walls == walls {
+            return;
+        }
+
+        if recenter {
+            self.move_window(
+                (goal.0 - WINDOW_SIZE / 2, goal.1 - WINDOW_SIZE / 2),
+                colliders,
+            );
+        }
+        if self.walls != walls {
+            self.update_walls(walls, colliders);
+        }
+        self.has_obstacles = self.blocked.iter().any(|blocked| *blocked);
+        self.goal = Some(goal);
+        if self.has_obstacles {
+            self.compute_costs(goal);
+            self.compute_directions();
+        }
+    }
+
+    /// Move the window to `origin`, keeping the cells it still covers.
+    fn move_window(&mut self, origin: Cell, colliders: &ColliderSet) {
+        let old_origin = self.origin;
+        let cells = self.blocked.len();
+        let old_blocked = std::mem::replace(&mut self.blocked, vec![false; cells]);
+        self.origin = origin;
+        for index in 0..self.blocked.len() {
+            if let Some(old_index) = window_index(old_origin, self.cell_at(index)) {
+                self.blocked[index] = old_blocked[old_index];
+            }
+        }
+
+        // The cells that entered, in a column strip and a row strip
+        let (last_x, last_y) = (origin.0 + WINDOW_SIZE - 1, origin.1 + WINDOW_SIZE - 1);
+        let columns = if origin.0 < old_origin.0 {
+            (origin.0, old_origin.0 - 1)
+        } else {
+            (old_origin.0 + WINDOW_SIZE, last_x)
+        };
+        let rows = if origin.1 < old_origin.1 {
+            (origin.1, old_origin.1 - 1)
+        } else {
+            (old_origin.1 + WINDOW_SIZE, last_y)
+        };
+        self.resample(((columns.0, origin.1), (columns.1, last_y)), colliders);
+        self.resample(((origin.0, rows.0), (last_x, rows.1)), colliders);
+    }
+
+    /// Take the new set of `walls`, sampling the cells of those added or removed.
+    /// Walls don't move, their cells are only sampled when they are added.
+    fn update_walls(&mut self, walls: Vec<ColliderHandle>, colliders: &ColliderSet) {
+        let removed: Vec<Area> = self
+            .walls
+            .iter()
+            .filter(|handle| !walls.contains(handle))
+            .filter_map(|handle| self.wall_areas.remove(handle))
+            .collect();
+        let added: Vec<ColliderHandle> = walls
+            .iter()
+            .filter(|handle| !self.walls.contains(handle))
+            .copied()
+            .collect();
+        self.walls = walls;
+
+        for handle in added {
+            if let Some(wall) = colliders.get(handle) {
+                let aabb = wall.compute_aabb();
+                let area = (
+                    cell_of(vec2(aabb.mins.x, aabb.mins.y)),
+                    cell_of(vec2(aabb.maxs.x, aabb.maxs.y)),
+                );
+                self.wall_areas.insert(handle, area);
+                self.resample(area, colliders);
+            }
+        }
+        for area in removed {
+            self.resample(area, colliders);
+        }
+    }
+
+    /// Sample the cells of `area` in the window again, against the walls over them.
+    fn resample(&mut self, area: Area, colliders: &ColliderSet) {
+        let window = (
+            self.origin,
+            (
+                self.origin.0 + WINDOW_SIZE - 1,
+                self.origin.1 + WINDOW_SIZE - 1,
+            ),
+        );
+        let Some(area) = intersection(area, window) else {
+            return;
+        };
+        for y in (area.0).1..=(area.1).1 {
+            for x in (area.0).0..=(area.1).0 {
+                if let Some(index) = self.index((x, y)) {
+                    self.blocked[index] = false;
+                }
+            }
+        }
+
+        // A bit smaller than the cell, a wall along its edge leaves it free
+        let cell_shape = Cuboid::new(vector![
+            FLOW_CELL_SIZE / 2.0 - 1.0,
+            FLOW_CELL_SIZE / 2.0 - 1.0
+        ]);
+        for handle in self.walls.iter() {
+            let (Some(wall), Some(cells)) = (
+                colliders.get(*handle),
+                self.wall_areas
+                    .get(handle)
+                    .and_then(|wall_area| intersection(area, *wall_area)),
+            ) else {
+                continue;
+            };
+            for y in (cells.0).1..=(cells.1).1 {
+                for x in (cells.0).0..=(cells.1).0 {
+                    let Some(index) = self.index((x, y)) else {
+                        continue;
+                    };
+                    let center = cell_center((x, y));
+                    self.blocked[index] |= rapier2d::parry::query::intersection_test(
+                        &Isometry::translation(center.x, center.y),
+                        &cell_shape,
+                        wall.position(),
+                        wall.shape(),
+                    )
+                    // Shapes parry can't test against a box block their whole bounding box
+                    .unwrap_or(true);
+                }
+            }
+        }
+    }
+
+    /// Dijkstra from the goal over the free cells.
+    fn compute_costs(&mut self, goal: Cell) {
+        self.costs.fill(u32::MAX);
+        let Some(start) = self.index(goal) else {
+            return;
+        };
+        self.costs[start] = 0;
+        // Ties pop the lowest index first, so the field doesn't depend on anything else
+        let mut queue = BinaryHeap::from([Reverse((0, start))]);
+        let mut neighbours = Vec::with_capacity(NEIGHBOURS.len());
+
+        while let Some(Reverse((cost, index))) = queue.pop() {
+            if cost > self.costs[index] {
+                continue;
+            }
+            neighbours.clear();
+            neighbours.extend(self.free_neighbours(self.cell_at(index)));
+            for &(neighbour, step) in &neighbours {
+                let next_cost = cost + step;
+                if next_cost < self.costs[neighbour] {
+                    self.costs[neighbour] = next_cost;
+                    queue.push(Reverse((next_cost, neighbour)));
+                }
+            }
+        }
+    }
+
+    /// Free cells next to `cell`, with the cost of the step.
+    /// Diagonals need both straight cells free, so paths don't cut wall corners.
+    fn free_neighbours(&self, cell: Cell) -> impl Iterator<Item = (usize, u32)> + '_ {
+        NEIGHBOURS.iter().filter_map(move |&(dx, dy)| {
+            let free = |cell: Cell| self.index(cell).filter(|index| !self.blocked[*index]);
+            let neighbour = free((cell.0 + dx, cell.1 + dy))?;
+            if dx != 0 && dy != 0 {
+                free((cell.0 + dx, cell.1))?;
+                free((cell.0, cell.1 + dy))?;
+                Some((neighbour, DIAGONAL_STEP))
+            } else {
+                Some((neighbour, STEP))
+            }
+        })
+    }
+
+    fn compute_directions(&mut self) {
+        for index in 0..self.directions.len() {
+            let cost = self.costs[index];
+            self.directions[index] = Vec2::ZERO;
+            if cost == 0 || cost == u32::MAX {
+                continue;
+            }
+            let cell = self.cell_at(index);
+            let best = self
+                .free_neighbours(cell)
+                .filter(|(neighbour, _step)| self.costs[*neighbour] < cost)
+                .min_by_key(|(neighbour, _step)| (self.costs[*neighbour], *neighbour));
+            if let Some((neighbour, _step)) = best {
+                self.directions[index] =
+                    (cell_center(self.cell_at(neighbour)) - cell_center(cell)).normalize();
+            }
+        }
+    }
+
+    /// Center and direction of every cell leading to the player, for the debug overlay.
+    pub fn arrows(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.directions
+            .iter()
+            .enumerate()
+            .filter(|(_index, direction)| **direction != Vec2::ZERO)
+            .map(|(index, direction)| (cell_center(self.cell_at(index)), *direction))
+    }
+}
+
+/// Follow the player with the flow field.
+pub fn flow_field_system(world: &World, physics: &PhysicsResources, field: &mut FlowField) {
+    let Some(player_center) = world
+        .query::<&ColliderHandleComponent>()
+        .with::<&Player>()
+        .iter()
+        .find_map(|(_id, handle)| collider_center(physics, handle))
+    else {
+        return;
+    };
+
+    let walls = world
+        .query::<(&PhysicsLayer, &ColliderHandleComponent)>()
+        .iter()
+        .filter(|(_id, (layer, _handle))| **layer == PhysicsLayer::Wall)
+        .map(|(_id, (_layer, handle))| handle.0)
+        .collect();
+    field.update(player_center, walls, &physics.collider_set);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Field to the player on cell (0, 0), with a wall on the cells (4, -4) to (4, 4).
+    fn field_around_wall() -> FlowField {
+        let mut colliders = ColliderSet::new();
+        let wall = colliders.insert(
+            ColliderBuilder::cuboid(FLOW_CELL_SIZE / 2.0, FLOW_CELL_SIZE * 4.5)
+                .translation(vector![4.5 * FLOW_CELL_SIZE, 0.5 * FLOW_CELL_SIZE]),
+        );
+        let mut field = FlowField::default();
+        field.update(cell_center((0, 0)), vec![wall], &colliders);
+        field
+    }
+
+    fn is_blocked(field: &FlowField, cell: Cell) -> bool {
+        field.index(cell).is_some_and(|index| field.blocked[index])
+    }
+
+    #[test]
+    fn blocks_the_cells_under_the_wall() {
+        let field = field_around_wall();
+        assert!(field.has_obstacles());
+        for y in -4..=4 {
+            assert!(is_blocked(&field, (4, y)), "(4, {}) should be blocked", y);
+        }
+        for cell in [(4, -5), (4, 5), (3, 0), (5, 0)] {
+            assert!(!is_blocked(&field, cell), "{:?} should be free", cell);
+        }
+    }
+
+    #[test]
+    fn routes_around_the_wall_without_cutting_corners() {
+        let field = field_around_wall();
+        let mut cell = (8, 0);
+        let mut farthest = 0;
+        for _ in 0..64 {
+            let Some(direction) = field.direction_at(cell_center(cell)) else {
+                break;
+            };
+            let step = (direction * std::f32::consts::SQRT_2).round();
+            let (dx, dy) = (step.x as i32, step.y as i32);
+            let next = (cell.0 + dx, cell.1 + dy);
+            assert!(!is_blocked(&field, next), "{:?} walks into the wall", cell);
+            if dx != 0 && dy != 0 {
+                assert!(
+                    !is_blocked(&field, (cell.0 + dx, cell.1))
+                        && !is_blocked(&field, (cell.0, cell.1 + dy)),
+                    "{:?} cuts a corner of the wall",
+                    cell
+                );
+            }
+            cell = next;
+            farthest = farthest.max(cell.1.abs());
+        }
+        assert_eq!(cell, (0, 0));
+        // Around an end of the wall, not through it
+        assert!(farthest >= 5);
+    }
+
+    #[test]
+    fn walks_straight_without_walls() {
+        let mut field = FlowField::default();
+        field.update(cell_center((0, 0)), Vec::new(), &ColliderSet::new());
+        assert!(!field.has_obstacles());
+        assert_eq!(field.direction_at(cell_center((8, 0))), None);
+    }
+
+    #[test]
+    fn refreshes_like_a_new_field() {
+        let mut colliders = ColliderSet::new();
+        let walls: Vec<ColliderHandle> = [(40, 0), (-30, 10), (10, 50)]
+            .into_iter()
+            .map(|(x, y)| {
+                colliders.insert(
+                    ColliderBuilder::cuboid(FLOW_CELL_SIZE * 1.5, FLOW_CELL_SIZE * 1.5)
+                        .translation(vector![
+                            x as f32 * FLOW_CELL_SIZE,
+                            y as f32 * FLOW_CELL_SIZE
+                        ]),
+                )
+            })
+            .collect();
+
+        // Move the window twice, then drop a wall
+        let mut field = FlowField::default();
+        for (goal, walls) in [
+            ((0, 0), walls.clone()),
+            ((20, 5), walls.clone()),
+            ((2, 30), walls.clone()),
+            ((2, 30), walls[..2].to_vec()),
+        ] {
+            field.update(cell_center(goal), walls, &colliders);
+        }
+
+        let mut fresh = FlowField::default();
+        fresh.update(cell_center((2, 30)), walls[..2].to_vec(), &colliders);
+        assert!(fresh.has_obstacles());
+        assert_eq!(field.origin, fresh.origin);
+        assert_eq!(field.blocked, fresh.blocked);
+        assert_eq!(field.costs, fresh.costs);
+    }
+}
@@ -0,0 +1,222 @@
+//! Run the simulation without a window, for CI and tests.
+//!
+//! `vamp-survivor --headless [--ticks N] [--seed SEED] [--input FILE] [--record FILE]`
+//! `vamp-survivor --headless --replay FILE`
+//!
+//! The input file scripts the player movement, one `tick x y` line per change:
+//! from `tick`, the player moves in the `(x, y)` direction. Lines starting with `#` are ignored.
+//!
+//! With `--replay`, the replay is played to its end and the process exits with an error on desync.
+//!
+//! Machines without a sound card build it with `cargo build --no-default-features`.
+use log::error;
+use macroquad::prelude::*;
+
+use crate::{
+    components::{Enemy, Health, Player},
+    input::PlayerInput,
+    progression::{Experience, Level},
+    replay::{ReplayPlayer, ReplayRecorder, ReplayStep},
+    schedule::Resources,
+    simulation::{GameData, Run},
+    state::GameState,
+    weapon::Weapons,
+};
+
+/// Two minutes of game at the default tick rate.
+const DEFAULT_TICKS: u32 = 32 * 120;
+
+/// Player movement keyed by tick.
+#[derive(Default)]
+pub struct InputScript {
+    keys: Vec<(u32, Vec2)>,
+}
+
+impl InputScript {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut keys = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match fields.as_slice() {
+                [tick, x, y] => tick
+                    .parse::<u32>()
+                    .ok()
+                    .zip(x.parse::<f32>().ok())
+                    .zip(y.parse::<f32>().ok())
+                    .map(|((tick, x), y)| (tick, vec2(x, y))),
+                _ => None,
+            };
+            match parsed {
+                Some(key) => keys.push(key),
+                None => return Err(format!("line {}: expected `tick x y`", number + 1)),
+            }
+        }
+        keys.sort_by_key(|(tick, _movement)| *tick);
+
+        Ok(Self { keys })
+    }
+
+    pub fn input_at(&self, tick: u32) -> PlayerInput {
+        let movement = self
+            .keys
+            .iter()
+            .take_while(|(key_tick, _movement)| *key_tick <= tick)
+            .last()
+            .map(|(_tick, movement)| *movement)
+            .unwrap_or(Vec2::ZERO);
+
+        PlayerInput { movement }
+    }
+}
+
+/// Parse the command line, run the simulation and print a summary.
+pub fn run(args: &[String]) {
+    let mut ticks = DEFAULT_TICKS;
+    let mut seed = 0;
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut script = InputScript::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => ticks = value,
+                None => {
+                    error!("--ticks expects a number of ticks");
+                    std::process::exit(2);
+                }
+            },
+            "--seed" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => seed = value,
+                None => {
+                    error!("--seed expects a number");
+                    std::process::exit(2);
+                }
+            },
+            "--input" => {
+                let Some(path) = args.next() else {
+                    error!("--input expects a file");
+                    std::process::exit(2);
+                };
+                let parsed = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|content| InputScript::parse(&content));
+                match parsed {
+                    Ok(parsed) => script = parsed,
+                    Err(e) => {
+                        error!("Loading input script {}: {}", path, e);
+                        std::process::exit(2);
+                    }
+                }
+            }
+            "--record" => record_path = args.next().cloned(),
+            "--replay" => replay_path = args.next().cloned(),
+            _ => {}
+        }
+    }
+
+    let mut game = Resources::default();
+    game.insert(GameData::load());
+    if let Some(path) = replay_path {
+        play_replay(&path, &game);
+        return;
+    }
+
+    let mut run = Run::new(seed);
+    if record_path.is_some() {
+        run.recorder = Some(ReplayRecorder::new(seed));
+    }
+
+    let mut state = GameState::Playing;
+    for tick in 0..ticks {
+        // Every tick is a frame of its own
+        run.start_frame();
+        state = run.tick(&script.input_at(tick), &game);
+        // No one to choose: always take the first upgrade.
+        while state == GameState::LevelUp {
+            state = run.choose_upgrade(0);
+        }
+        if state == GameState::GameOver {
+            break;
+        }
+    }
+
+    if let (Some(path), Some(recorder)) = (record_path, run.recorder.as_ref()) {
+        match recorder.save(&path) {
+            Ok(()) => println!("Replay saved: {}", path),
+            Err(e) => {
+                error!("Saving replay {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    print_summary(&run, state);
+}
+
+/// Play a replay to its end, checking every recorded state hash.
+fn play_replay(path: &str, game: &Resources) {
+    let mut player = match ReplayPlayer::load(path) {
+        Ok(player) => player,
+        Err(e) => {
+            error!("Loading replay {}: {}", path, e);
+            std::process::exit(2);
+        }
+    };
+    let mut run = Run::new(player.seed());
+
+    let mut state = GameState::Playing;
+    loop {
+        match player.next_step(&mut run) {
+            Ok(ReplayStep::Tick(input)) => {
+                run.start_frame();
+                state = run.tick(&input, game);
+            }
+            Ok(ReplayStep::Choice(index)) => state = run.choose_upgrade(index),
+            Ok(ReplayStep::End) => break,
+            Err(e) => {
+                error!("Replay {}: {}", path, e);
+                print_summary(&run, state);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    println!("Replay OK: {}", path);
+    print_summary(&run, state);
+}
+
+fn print_summary(run: &Run, state: GameState) {
+    let seconds = run.elapsed() as u32;
+    println!("Seed: {}", run.seed());
+    println!("Ticks simulated: {}", run.ticks_elapsed());
+    println!("Time survived: {:02}:{:02}", seconds / 60, seconds % 60);
+    println!("Player dead: {}", state == GameState::GameOver);
+    println!("Enemies killed: {}", run.kills());
+    println!(
+        "Enemies alive: {}",
+        run.world.query::<&Enemy>().iter().count()
+    );
+
+    for (_id, (health, level, experience, weapons)) in run
+        .world
+        .query::<(&Health, &Level, &Experience, &Weapons)>()
+        .with::<&Player>()
+        .iter()
+    {
+        println!("Player health: {:.0}/{:.0}", health.actual, health.max);
+        println!(
+            "Player level: {} ({}/{} xp)",
+            level.0, experience.current, experience.to_next
+        );
+        for weapon in weapons.0.iter() {
+            println!("Weapon: {} level {}", weapon.kind.name(), weapon.level);
+        }
+    }
+}
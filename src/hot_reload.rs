@@ -0,0 +1,80 @@
+//! Reload assets when their file changes on disk, in debug builds.
+//!
+//! Files are polled by modification time, which is enough for a few hundred assets
+//! and doesn't need a file system notification backend per platform.
+use std::{collections::HashMap, path::Path, time::SystemTime};
+
+use log::{error, info};
+
+/// Directory watched for changes.
+pub const WATCHED_DIR: &str = "assets";
+/// Time between two scans of the directory, in seconds.
+const POLL_INTERVAL: f64 = 0.5;
+
+/// Modification time of every file in `dir` and its subdirectories.
+/// Paths use `/`, like the paths given to the `AssetServer`, so they give the same `AssetId`.
+fn scan(dir: &Path, files: &mut HashMap<String, SystemTime>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Watching {}: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            scan(&path, files);
+        } else if let Ok(modified) = metadata.modified() {
+            files.insert(path.to_string_lossy().replace('\\', "/"), modified);
+        }
+    }
+}
+
+/// Watch a directory for modified or new files.
+pub struct AssetWatcher {
+    dir: String,
+    modified: HashMap<String, SystemTime>,
+    last_poll: f64,
+}
+
+impl AssetWatcher {
+    pub fn new(dir: &str) -> Self {
+        let mut modified = HashMap::new();
+        scan(Path::new(dir), &mut modified);
+        info!("Watching {} files in {}", modified.len(), dir);
+        Self {
+            dir: dir.to_owned(),
+            modified,
+            last_poll: 0.0,
+        }
+    }
+
+    /// Files modified or created since the last call, checked at most every `POLL_INTERVAL`.
+    /// `time` is the time since the start of the game, in seconds.
+    pub fn poll(&mut self, time: f64) -> Vec<String> {
+        if time - self.last_poll < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = time;
+
+        let mut files = HashMap::new();
+        scan(Path::new(&self.dir), &mut files);
+        let mut changed: Vec<String> = files
+            .iter()
+            .filter(|(path, modified)| self.modified.get(*path) != Some(*modified))
+            .map(|(path, _modified)| path.clone())
+            .collect();
+        changed.sort();
+        self.modified = files;
+
+        for path in changed.iter() {
+            info!("Asset changed: {}", path);
+        }
+        changed
+    }
+}
@@ -0,0 +1,669 @@
+//! Named actions bound to keys and gamepads.
+//!
+//! Systems ask the `InputMap` for actions, never for raw keys, so every binding can be changed
+//! from the user config file or at runtime from the controls screen.
+use std::{collections::BTreeMap, path::PathBuf};
+
+use log::{error, info};
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Axis values under this are ignored, worn sticks never rest exactly at zero.
+const AXIS_DEAD_ZONE: f32 = 0.2;
+
+/// Player intentions for one update, decoupled from the keyboard so the simulation can run headless.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlayerInput {
+    /// Wanted move direction, of length at most 1.
+    pub movement: Vec2,
+}
+
+impl PlayerInput {
+    /// Each axis in `[-1, 1]` packed in a byte, as stored in replays.
+    pub fn to_bytes(self) -> [i8; 2] {
+        [self.movement.x, self.movement.y].map(|axis| (axis.clamp(-1.0, 1.0) * 127.0).round() as i8)
+    }
+
+    pub fn from_bytes(bytes: [i8; 2]) -> Self {
+        let [x, y] = bytes.map(|axis| axis as f32 / 127.0);
+        Self {
+            movement: vec2(x, y),
+        }
+    }
+
+    /// The input as it would come out of a replay.
+    /// Live runs play this one, so they replay exactly.
+    pub fn quantized(self) -> Self {
+        Self::from_bytes(self.to_bytes())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Pause,
+    Confirm,
+    ToggleDebug,
+    ZoomIn,
+    ZoomOut,
+    /// Pick the first upgrade on level-up.
+    Choice1,
+    Choice2,
+    Choice3,
+    /// Open the controls screen from the pause menu.
+    Controls,
+    ReplayPause,
+    ReplayStep,
+    ReplaySpeed,
+}
+
+impl Action {
+    pub const ALL: [Action; 16] = [
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Pause,
+        Action::Confirm,
+        Action::ToggleDebug,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::Choice1,
+        Action::Choice2,
+        Action::Choice3,
+        Action::Controls,
+        Action::ReplayPause,
+        Action::ReplayStep,
+        Action::ReplaySpeed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Pause => "Pause / back",
+            Action::Confirm => "Confirm",
+            Action::ToggleDebug => "Toggle debug",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::Choice1 => "Upgrade 1",
+            Action::Choice2 => "Upgrade 2",
+            Action::Choice3 => "Upgrade 3",
+            Action::Controls => "Controls",
+            Action::ReplayPause => "Replay pause",
+            Action::ReplayStep => "Replay step",
+            Action::ReplaySpeed => "Replay speed",
+        }
+    }
+}
+
+/// Keys that can be bound, named like their `KeyCode` in the config file.
+const BINDABLE_KEYS: &[KeyCode] = &[
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Semicolon,
+    KeyCode::Equal,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::LeftBracket,
+    KeyCode::Backslash,
+    KeyCode::RightBracket,
+    KeyCode::GraveAccent,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::Pause,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpDecimal,
+    KeyCode::KpDivide,
+    KeyCode::KpMultiply,
+    KeyCode::KpSubtract,
+    KeyCode::KpAdd,
+    KeyCode::KpEnter,
+    KeyCode::LeftShift,
+    KeyCode::LeftControl,
+    KeyCode::LeftAlt,
+    KeyCode::RightShift,
+    KeyCode::RightControl,
+    KeyCode::RightAlt,
+];
+
+/// A keyboard key, stored by name in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Key(pub KeyCode);
+
+impl TryFrom<String> for Key {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        BINDABLE_KEYS
+            .iter()
+            .find(|key| format!("{:?}", key) == name)
+            .map(|key| Key(*key))
+            .ok_or_else(|| format!("unknown key {}", name))
+    }
+}
+
+impl From<Key> for String {
+    fn from(key: Key) -> Self {
+        format!("{:?}", key.0)
+    }
+}
+
+/// Gamepad buttons, named after their position on the pad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Gamepad sticks. Y points up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+}
+
+/// Half of an axis that triggers the action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Binding {
+    Key(Key),
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis, AxisDirection),
+}
+
+impl Binding {
+    /// Keys replace keys and buttons replace buttons when rebinding, sticks stay bound.
+    fn same_kind(&self, other: &Binding) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key.0),
+            Binding::GamepadButton(button) => format!("Pad {:?}", button),
+            Binding::GamepadAxis(axis, AxisDirection::Positive) => format!("Pad {:?}+", axis),
+            Binding::GamepadAxis(axis, AxisDirection::Negative) => format!("Pad {:?}-", axis),
+        }
+    }
+}
+
+/// Buttons and sticks of every connected gamepad, merged: any pad can play.
+/// Gamepads need the `gamepad` feature, they are never pressed without it.
+#[derive(Default)]
+struct GamepadState {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
+    down: Vec<GamepadButton>,
+    /// Buttons pressed since the last update.
+    pressed: Vec<GamepadButton>,
+    /// Indexed like `GamepadAxis`.
+    axes: [f32; 4],
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadState {
+    const BUTTONS: [GamepadButton; 12] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::North,
+        GamepadButton::West,
+        GamepadButton::LeftTrigger,
+        GamepadButton::RightTrigger,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::DPadUp,
+        GamepadButton::DPadDown,
+        GamepadButton::DPadLeft,
+        GamepadButton::DPadRight,
+    ];
+    const AXES: [GamepadAxis; 4] = [
+        GamepadAxis::LeftStickX,
+        GamepadAxis::LeftStickY,
+        GamepadAxis::RightStickX,
+        GamepadAxis::RightStickY,
+    ];
+
+    fn new() -> Self {
+        let gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                error!("Gamepads unavailable: {}", e);
+                None
+            }
+        };
+        Self {
+            gilrs,
+            ..Default::default()
+        }
+    }
+
+    fn to_gilrs_button(button: GamepadButton) -> gilrs::Button {
+        match button {
+            GamepadButton::South => gilrs::Button::South,
+            GamepadButton::East => gilrs::Button::East,
+            GamepadButton::North => gilrs::Button::North,
+            GamepadButton::West => gilrs::Button::West,
+            GamepadButton::LeftTrigger => gilrs::Button::LeftTrigger,
+            GamepadButton::RightTrigger => gilrs::Button::RightTrigger,
+            GamepadButton::Select => gilrs::Button::Select,
+            GamepadButton::Start => gilrs::Button::Start,
+            GamepadButton::DPadUp => gilrs::Button::DPadUp,
+            GamepadButton::DPadDown => gilrs::Button::DPadDown,
+            GamepadButton::DPadLeft => gilrs::Button::DPadLeft,
+            GamepadButton::DPadRight => gilrs::Button::DPadRight,
+        }
+    }
+
+    fn to_gilrs_axis(axis: GamepadAxis) -> gilrs::Axis {
+        match axis {
+            GamepadAxis::LeftStickX => gilrs::Axis::LeftStickX,
+            GamepadAxis::LeftStickY => gilrs::Axis::LeftStickY,
+            GamepadAxis::RightStickX => gilrs::Axis::RightStickX,
+            GamepadAxis::RightStickY => gilrs::Axis::RightStickY,
+        }
+    }
+
+    fn update(&mut self) {
+        self.pressed.clear();
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            if let gilrs::EventType::ButtonPressed(button, _code) = event.event {
+                if let Some(button) = Self::BUTTONS
+                    .into_iter()
+                    .find(|candidate| Self::to_gilrs_button(*candidate) == button)
+                {
+                    self.pressed.push(button);
+                }
+            }
+        }
+
+        self.down.clear();
+        self.axes = [0.0; 4];
+        for (_id, gamepad) in gilrs.gamepads() {
+            for button in Self::BUTTONS {
+                if gamepad.is_pressed(Self::to_gilrs_button(button)) && !self.down.contains(&button)
+                {
+                    self.down.push(button);
+                }
+            }
+            for (value, axis) in self.axes.iter_mut().zip(Self::AXES) {
+                let axis_value = gamepad.value(Self::to_gilrs_axis(axis));
+                if axis_value.abs() > value.abs() {
+                    *value = axis_value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+impl GamepadState {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&mut self) {}
+}
+
+/// Name of the user bindings file, in the user config directory.
+pub const BINDINGS_FILE: &str = "input.ron";
+
+/// Path of `file` in the user config directory.
+pub fn config_path(file: &str) -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .unwrap_or_default();
+    config_dir.join("vamp-survivor").join(file)
+}
+
+/// Bindings of every action, and the input devices they read.
+pub struct InputMap {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+    gamepad: GamepadState,
+}
+
+impl InputMap {
+    /// WASD, ZQSD and the arrows all move, so QWERTY and AZERTY both work out of the box.
+    /// No key or button is bound twice. Pads have fewer buttons than there are actions: zoom is
+    /// on the right stick, debug and replay speed are keyboard only.
+    pub fn default_bindings() -> BTreeMap<Action, Vec<Binding>> {
+        use Binding::{GamepadAxis as Axis, GamepadButton as Button};
+        let key = |key| Binding::Key(Key(key));
+
+        BTreeMap::from([
+            (
+                Action::MoveUp,
+                vec![
+                    key(KeyCode::W),
+                    key(KeyCode::Z),
+                    key(KeyCode::Up),
+                    Button(GamepadButton::DPadUp),
+                    Axis(GamepadAxis::LeftStickY, AxisDirection::Positive),
+                ],
+            ),
+            (
+                Action::MoveDown,
+                vec![
+                    key(KeyCode::S),
+                    key(KeyCode::Down),
+                    Button(GamepadButton::DPadDown),
+                    Axis(GamepadAxis::LeftStickY, AxisDirection::Negative),
+                ],
+            ),
+            (
+                Action::MoveLeft,
+                vec![
+                    key(KeyCode::A),
+                    key(KeyCode::Q),
+                    key(KeyCode::Left),
+                    Button(GamepadButton::DPadLeft),
+                    Axis(GamepadAxis::LeftStickX, AxisDirection::Negative),
+                ],
+            ),
+            (
+                Action::MoveRight,
+                vec![
+                    key(KeyCode::D),
+                    key(KeyCode::Right),
+                    Button(GamepadButton::DPadRight),
+                    Axis(GamepadAxis::LeftStickX, AxisDirection::Positive),
+                ],
+            ),
+            (
+                Action::Pause,
+                vec![key(KeyCode::Escape), Button(GamepadButton::Start)],
+            ),
+            (
+                Action::Confirm,
+                vec![key(KeyCode::Enter), Button(GamepadButton::South)],
+            ),
+            (Action::ToggleDebug, vec![key(KeyCode::F3)]),
+            (
+                Action::ZoomIn,
+                vec![
+                    key(KeyCode::Equal),
+                    key(KeyCode::KpAdd),
+                    Axis(GamepadAxis::RightStickY, AxisDirection::Positive),
+                ],
+            ),
+            (
+                Action::ZoomOut,
+                vec![
+                    key(KeyCode::Minus),
+                    key(KeyCode::KpSubtract),
+                    Axis(GamepadAxis::RightStickY, AxisDirection::Negative),
+                ],
+            ),
+            (
+                Action::Choice1,
+                vec![key(KeyCode::Key1), Button(GamepadButton::West)],
+            ),
+            (
+                Action::Choice2,
+                vec![key(KeyCode::Key2), Button(GamepadButton::North)],
+            ),
+            (
+                Action::Choice3,
+                vec![key(KeyCode::Key3), Button(GamepadButton::East)],
+            ),
+            (
+                Action::Controls,
+                vec![key(KeyCode::C), Button(GamepadButton::Select)],
+            ),
+            (
+                Action::ReplayPause,
+                vec![key(KeyCode::Space), Button(GamepadButton::RightTrigger)],
+            ),
+            (
+                Action::ReplayStep,
+                vec![key(KeyCode::Period), Button(GamepadButton::LeftTrigger)],
+            ),
+            (Action::ReplaySpeed, vec![key(KeyCode::F)]),
+        ])
+    }
+
+    /// Load the user bindings from `path`, over the defaults.
+    /// A missing or broken file leaves the defaults.
+    pub fn load(path: &PathBuf) -> Self {
+        let mut bindings = Self::default_bindings();
+
+        match std::fs::read_to_string(path) {
+            Ok(content) => match ron::from_str::<BTreeMap<Action, Vec<Binding>>>(&content) {
+                Ok(user_bindings) => bindings.extend(user_bindings),
+                Err(e) => error!("Loading bindings {}: {}", path.display(), e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("No bindings at {}, using the defaults", path.display());
+            }
+            Err(e) => error!("Loading bindings {}: {}", path.display(), e),
+        }
+
+        Self {
+            bindings,
+            gamepad: GamepadState::new(),
+        }
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let content = ron::ser::to_string_pretty(&self.bindings, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, content).map_err(|e| e.to_string())
+    }
+
+    /// Poll the gamepads. Call once per frame, before querying actions.
+    pub fn update(&mut self) {
+        self.gamepad.update();
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Name of the first binding of `action`, for on-screen hints.
+    pub fn label(&self, action: Action) -> String {
+        self.bindings(action)
+            .first()
+            .map(Binding::name)
+            .unwrap_or_else(|| "unbound".to_owned())
+    }
+
+    /// Bind `binding` to `action`, in place of its first binding of the same kind.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        bindings.retain(|existing| *existing != binding);
+        match bindings
+            .iter_mut()
+            .find(|existing| existing.same_kind(&binding))
+        {
+            Some(existing) => *existing = binding,
+            None => bindings.insert(0, binding),
+        }
+        info!("{} bound to {}", action.name(), binding.name());
+    }
+
+    fn binding_value(&self, binding: &Binding) -> f32 {
+        match binding {
+            Binding::Key(key) => is_key_down(key.0) as u8 as f32,
+            Binding::GamepadButton(button) => self.gamepad.down.contains(button) as u8 as f32,
+            Binding::GamepadAxis(axis, direction) => {
+                let value = self.gamepad.axes[*axis as usize];
+                let value = match direction {
+                    AxisDirection::Positive => value,
+                    AxisDirection::Negative => -value,
+                };
+                ((value - AXIS_DEAD_ZONE) / (1.0 - AXIS_DEAD_ZONE)).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    /// How much the action is held, in `[0, 1]`. Keys and buttons are 0 or 1, sticks are analog.
+    pub fn value(&self, action: Action) -> f32 {
+        self.bindings(action)
+            .iter()
+            .map(|binding| self.binding_value(binding))
+            .fold(0.0, f32::max)
+    }
+
+    /// Returns `true` on the frame the action is triggered.
+    /// Only keys and buttons trigger, sticks are held.
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.bindings(action).iter().any(|binding| match binding {
+            Binding::Key(key) => is_key_pressed(key.0),
+            Binding::GamepadButton(button) => self.gamepad.pressed.contains(button),
+            Binding::GamepadAxis(..) => false,
+        })
+    }
+
+    /// Key or button pressed this frame, to rebind an action to it.
+    pub fn pressed_binding(&self) -> Option<Binding> {
+        BINDABLE_KEYS
+            .iter()
+            .find(|key| is_key_pressed(**key))
+            .map(|key| Binding::Key(Key(*key)))
+            .or_else(|| {
+                self.gamepad
+                    .pressed
+                    .first()
+                    .map(|button| Binding::GamepadButton(*button))
+            })
+    }
+
+    /// Read the player input from the bound keys and sticks.
+    pub fn player_input(&self) -> PlayerInput {
+        let movement = vec2(
+            self.value(Action::MoveRight) - self.value(Action::MoveLeft),
+            self.value(Action::MoveDown) - self.value(Action::MoveUp),
+        );
+
+        PlayerInput {
+            movement: movement.clamp_length_max(1.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_are_unique() {
+        let bindings = InputMap::default_bindings();
+        let mut seen: Vec<(Binding, Action)> = Vec::new();
+        for (action, action_bindings) in &bindings {
+            for binding in action_bindings {
+                if let Some((_, other)) = seen.iter().find(|(existing, _)| existing == binding) {
+                    panic!(
+                        "{} is bound to {:?} and {:?}",
+                        binding.name(),
+                        other,
+                        action
+                    );
+                }
+                seen.push((*binding, *action));
+            }
+        }
+    }
+}
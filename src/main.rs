@@ -1,28 +1,65 @@
-use hecs::World;
 use macroquad::prelude::*;
 
 use crate::{
-    asset_server::AssetServer,
-    components::GameTick,
-    debug::{DebugData, debug_infos_system},
-    enemy::{EnemySpawner, enemy_ai_system, enemy_spawner_system},
-    physic::{
-        collision_register, physics_cleanup_system, physics_step_system, setup_physics,
-        sync_physics_world, sync_transforms,
+    animation::{Corpses, animation_state_system, animation_system, corpse_system},
+    asset_manifest::{AssetKind, AssetManifest, MANIFEST_PATH},
+    asset_server::{AssetServer, assets},
+    audio::{AudioManager, AudioSettings, SETTINGS_FILE, Volume, music_for},
+    camera::{CameraController, player_center},
+    components::{GameTick, Player},
+    debug::{
+        DebugWorld, debug_display_enabled, debug_draw_colliders_system,
+        debug_draw_flow_field_system, debug_infos_system, toggle_debug_display,
     },
-    player::{detect_player_dead, player_input_system, spawn_player},
+    events::{DamageDealt, Events},
+    flow_field::FlowField,
+    hot_reload::{AssetWatcher, WATCHED_DIR},
+    input::{Action, BINDINGS_FILE, InputMap, config_path},
+    physic::PhysicsResources,
     render::draw_world,
+    replay::{ReplayPlayer, ReplayRecorder, ReplayStep},
+    schedule::{ResourceLayers, Resources, Schedule, Stage, in_state, resource_exists, system},
+    simulation::{GameData, LevelUpChoices, Run, ViewHalfExtents},
+    state::GameState,
+    tilemap::{FLOOR_PATH, FloorDefinition, Tilemap},
+    ui::{
+        draw_controls_screen, draw_game_over_screen, draw_hud, draw_level_up_overlay,
+        draw_loading_screen, draw_pause_overlay, draw_replay_hud, draw_title_screen,
+    },
 };
 
-use crate::debug::{DebugLines, debug_draw_colliders_system};
 mod debug;
 
+mod animation;
+mod aseprite;
+mod asset_manifest;
 mod asset_server;
+mod audio;
+mod boss;
+mod camera;
+mod collision;
 mod components;
+mod damage;
+mod director;
 mod enemy;
+mod events;
+mod flow_field;
+mod headless;
+mod hot_reload;
+mod input;
 mod physic;
 mod player;
+mod progression;
 mod render;
+mod replay;
+mod schedule;
+mod simulation;
+mod spatial;
+mod state;
+mod steering;
+mod tilemap;
+mod ui;
+mod weapon;
 
 fn window_conf() -> Conf {
     Conf {
@@ -32,62 +69,731 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
     env_logger::init();
 
-    let mut world = World::new();
-    let mut physics_ressources = setup_physics();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        headless::run(&args);
+        return;
+    }
+
+    macroquad::Window::from_config(window_conf(), game(Options::parse(&args)));
+}
+
+/// Command line options of the windowed game.
+#[derive(Default)]
+struct Options {
+    /// Record every run to this replay file.
+    record: Option<String>,
+    /// Play this replay instead of a live run.
+    replay: Option<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Self {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record = args.next().cloned(),
+                "--replay" => options.replay = args.next().cloned(),
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+/// Replay playback: pause, step one tick while paused, and change the speed.
+/// A resource of the run while replaying.
+struct ReplayControls {
+    player: ReplayPlayer,
+    speed: f32,
+    paused: bool,
+    finished: bool,
+}
+
+impl ReplayControls {
+    /// Play the replay for one frame.
+    /// Fails loudly on desync, the whole point of a replay is to reproduce the exact run.
+    fn update(&mut self, run: &mut Run, game: &Resources) -> GameState {
+        run.start_frame();
+        let step = {
+            let input = game.get::<InputMap>();
+            if input.is_pressed(Action::ReplayPause) {
+                self.paused = !self.paused;
+            }
+            if input.is_pressed(Action::ReplaySpeed) {
+                self.speed = if self.speed >= 16.0 {
+                    1.0
+                } else {
+                    self.speed * 4.0
+                };
+            }
+            input.is_pressed(Action::ReplayStep)
+        };
+
+        let mut ticks = 0;
+        if self.paused {
+            if step {
+                ticks = 1;
+            }
+        } else {
+            run.resources.get_mut::<GameTick>().accumulator += get_frame_time() * self.speed;
+            while run.take_tick() {
+                ticks += 1;
+            }
+        }
+
+        let mut state = GameState::Playing;
+        while ticks > 0 && !self.finished && state != GameState::GameOver {
+            match self.player.next_step(run) {
+                Ok(ReplayStep::Tick(input)) => {
+                    state = run.tick(&input, game);
+                    ticks -= 1;
+                }
+                Ok(ReplayStep::Choice(index)) => state = run.choose_upgrade(index),
+                Ok(ReplayStep::End) => self.finished = true,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        run.interpolate();
+
+        // The upgrade choice is the next step of the replay
+        if state == GameState::LevelUp {
+            GameState::Playing
+        } else {
+            state
+        }
+    }
+}
+
+/// Start a new run, with the debug entities in debug builds.
+/// Replays give the seed, otherwise it is random.
+fn new_run(options: &Options) -> Run {
+    let replay = options
+        .replay
+        .as_ref()
+        .map(|path| match ReplayPlayer::load(path) {
+            Ok(player) => ReplayControls {
+                player,
+                speed: 1.0,
+                paused: false,
+                finished: false,
+            },
+            Err(e) => panic!("Loading replay {}: {}", path, e),
+        });
+
+    // Only the seed comes from the clock, the run itself is deterministic.
+    let seed = match replay.as_ref() {
+        Some(replay) => replay.player.seed(),
+        None => (miniquad::date::now() * 1000.0) as u64,
+    };
+    log::info!("Starting run with seed {}", seed);
+    let mut run = Run::new(seed);
+
+    match replay {
+        Some(replay) => run.resources.insert(replay),
+        None if options.record.is_some() => run.recorder = Some(ReplayRecorder::new(seed)),
+        None => {}
+    }
+    run.resources.insert(Corpses::default());
+    run
+}
+
+/// Pause screen: pick a volume, and turn it up or down.
+#[derive(Default)]
+struct PauseMenu {
+    /// Index of the selected volume in `Volume::ALL`.
+    selected: usize,
+}
+
+impl PauseMenu {
+    /// Navigate and change the volumes for one frame. Changes are saved right away.
+    fn update(&mut self, input: &InputMap, audio: &mut AudioManager) {
+        if input.is_pressed(Action::MoveUp) {
+            self.selected = (self.selected + Volume::ALL.len() - 1) % Volume::ALL.len();
+        }
+        if input.is_pressed(Action::MoveDown) {
+            self.selected = (self.selected + 1) % Volume::ALL.len();
+        }
+
+        let steps =
+            input.is_pressed(Action::MoveRight) as i32 - input.is_pressed(Action::MoveLeft) as i32;
+        if steps != 0 {
+            audio.settings.change(Volume::ALL[self.selected], steps);
+            let path = config_path(SETTINGS_FILE);
+            if let Err(e) = audio.settings.save(&path) {
+                log::error!("Saving audio settings {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Controls screen: pick an action, then press the key or button to bind to it.
+#[derive(Default)]
+struct ControlsMenu {
+    /// Index of the selected action in `Action::ALL`.
+    selected: usize,
+    /// Waiting for the new binding of the selected action.
+    listening: bool,
+}
+
+impl ControlsMenu {
+    /// Navigate and rebind for one frame.
+    /// Returns `true` when leaving the screen.
+    fn update(&mut self, input: &mut InputMap) -> bool {
+        if self.listening {
+            // Pause cancels, so the menu can always be left
+            if input.is_pressed(Action::Pause) {
+                self.listening = false;
+            } else if let Some(binding) = input.pressed_binding() {
+                input.rebind(Action::ALL[self.selected], binding);
+                self.listening = false;
+                let path = config_path(BINDINGS_FILE);
+                if let Err(e) = input.save(&path) {
+                    log::error!("Saving bindings {}: {}", path.display(), e);
+                }
+            }
+            return false;
+        }
+
+        if input.is_pressed(Action::MoveUp) {
+            self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len();
+        }
+        if input.is_pressed(Action::MoveDown) {
+            self.selected = (self.selected + 1) % Action::ALL.len();
+        }
+        if input.is_pressed(Action::Confirm) {
+            self.listening = true;
+        }
+        input.is_pressed(Action::Pause)
+    }
+}
+
+/// Center the camera on the player of a new run, and generate its floor.
+/// The frame readers of the events start over with the events of the run.
+fn start_run(run: &mut Run, game: &Resources) {
+    let center = player_center(&run.world, &run.resources.get::<PhysicsResources>());
+    game.get_mut::<CameraController>().start_run(center);
+    game.get_mut::<AudioManager>().start_run();
+    reset_tilemap(run, game);
+}
+
+/// Floor of the run, generated from its seed. None without a floor definition.
+fn reset_tilemap(run: &mut Run, game: &Resources) {
+    let tilemap = game
+        .try_get::<FloorDefinition>()
+        .map(|floor| Tilemap::new(floor.clone(), run.seed()));
+    match tilemap {
+        Some(tilemap) => run.resources.insert(tilemap),
+        None => {
+            run.resources.remove::<Tilemap>();
+        }
+    }
+}
+
+/// Apply the files changed on disk, without restarting the run.
+async fn hot_reload(
+    changed: &[String],
+    manifest: &AssetManifest,
+    run: &mut Run,
+    game: &mut Resources,
+) {
+    // Out of the resources while loading, borrows can't be held across an await
+    let mut asset_server = game.remove::<AssetServer>().expect("AssetServer resource");
+
+    // Reloaded assets keep their id, sprites show them right away
+    let assets: Vec<(&str, AssetKind)> = changed
+        .iter()
+        .filter_map(|path| manifest.get(path))
+        .map(|entry| (entry.path.as_str(), entry.kind))
+        .collect();
+    asset_server.load_assets(&assets).await;
+
+    // A recorded or replayed run must keep the data it started with, or it won't play the same
+    if run.recorder.is_some() || run.resources.try_get::<ReplayControls>().is_some() {
+        for path in changed
+            .iter()
+            .filter(|path| GameData::is_data_file(path) || *path == FLOOR_PATH)
+        {
+            log::warn!(
+                "{} changed, not reloaded while recording or replaying a run",
+                path
+            );
+        }
+        game.insert(asset_server);
+        return;
+    }
+
+    if changed.iter().any(|path| GameData::is_data_file(path)) {
+        game.insert(GameData::load());
+    }
+    if changed.iter().any(|path| path == FLOOR_PATH) {
+        match FloorDefinition::load(FLOOR_PATH) {
+            Some(floor) => game.insert(floor),
+            None => {
+                game.remove::<FloorDefinition>();
+            }
+        }
+        reset_tilemap(run, game);
+    }
+
+    // Assets the new data points to, the manifest is only checked at startup
+    let new_assets: Vec<(String, AssetKind)> = {
+        let data = game.get::<GameData>();
+        let floor = game.try_get::<FloorDefinition>();
+        asset_usages(&data, floor.as_deref())
+            .into_iter()
+            .filter(|(path, _kind)| !asset_server.is_loaded(path))
+            .map(|(path, kind)| (path.to_owned(), kind))
+            .collect()
+    };
+    let new_assets: Vec<(&str, AssetKind)> = new_assets
+        .iter()
+        .map(|(path, kind)| (path.as_str(), *kind))
+        .collect();
+    asset_server.load_assets(&new_assets).await;
+
+    game.insert(asset_server);
+}
+
+/// Every asset the code and the data use, to check the manifest against.
+fn asset_usages<'a>(
+    data: &'a GameData,
+    floor: Option<&'a FloorDefinition>,
+) -> Vec<(&'a str, AssetKind)> {
+    let mut usages = assets::usages();
+    usages.extend(
+        data.enemies
+            .sprite_paths()
+            .into_iter()
+            .map(|path| (path, AssetKind::Texture)),
+    );
+    usages.extend(floor.map(|floor| (floor.tileset.as_str(), AssetKind::Texture)));
+    usages
+}
+
+/// Write the replay of the run, if recording.
+fn save_replay(run: &Run, options: &Options) {
+    if let (Some(path), Some(recorder)) = (options.record.as_ref(), run.recorder.as_ref()) {
+        match recorder.save(path) {
+            Ok(()) => log::info!("Replay saved: {}", path),
+            Err(e) => log::error!("Saving replay {}: {}", path, e),
+        }
+    }
+}
+
+/// Time spent loading assets each frame, in seconds.
+const LOAD_BUDGET: f64 = 1.0 / 120.0;
+
+/// Screens drawn over the world of a run.
+const IN_RUN: &[GameState] = &[
+    GameState::Playing,
+    GameState::Paused,
+    GameState::Controls,
+    GameState::LevelUp,
+    GameState::GameOver,
+];
+
+/// Systems of every frame, around the state changes and the simulation.
+fn frame_schedule() -> Schedule {
+    let mut schedule = Schedule::default();
+    schedule.add_system(
+        Stage::PreUpdate,
+        system("input", |_world, resources| {
+            resources.get_mut::<InputMap>().update();
+        }),
+    );
+    if cfg!(debug_assertions) {
+        schedule.add_system(
+            Stage::PreUpdate,
+            system("toggle_debug", |_world, resources| {
+                if resources.get::<InputMap>().is_pressed(Action::ToggleDebug) {
+                    toggle_debug_display(&mut resources.get_mut::<DebugWorld>().0);
+                }
+            })
+            .after("input"),
+        );
+    }
+
+    schedule
+        .add_system(
+            Stage::Update,
+            system("camera", |world, resources| {
+                let mut camera = resources.get_mut::<CameraController>();
+                if let Some((player, _)) = world.query::<&Player>().iter().next() {
+                    camera.shake_on_hits(&resources.get::<Events<DamageDealt>>(), player);
+                }
+                camera.update(
+                    get_frame_time(),
+                    player_center(world, &resources.get::<PhysicsResources>()),
+                    &resources.get::<InputMap>(),
+                );
+            })
+            .run_if(in_state(&[GameState::Playing])),
+        )
+        // Events sent by the last tick of a run are heard on its last screen too
+        .add_system(
+            Stage::Update,
+            system("audio_events", |world, resources| {
+                let player = world.query::<&Player>().iter().next().map(|(id, _)| id);
+                resources.get_mut::<AudioManager>().play_events(
+                    resources,
+                    resources.get::<CameraController>().position,
+                    player,
+                    &resources.get::<AssetServer>(),
+                );
+            })
+            .after("camera")
+            .run_if(in_state(IN_RUN)),
+        )
+        .add_system(
+            Stage::Update,
+            system("animation_state", |world, resources| {
+                animation_state_system(world, &resources.get::<PhysicsResources>());
+            })
+            .run_if(in_state(&[GameState::Playing])),
+        )
+        .add_system(
+            Stage::Update,
+            system("animation", |world, resources| {
+                animation_system(world, &resources.get::<AssetServer>(), get_frame_time());
+            })
+            .after("animation_state")
+            .run_if(in_state(&[GameState::Playing])),
+        )
+        .add_system(
+            Stage::Update,
+            system("corpses", |_world, resources| {
+                let asset_server = resources.get::<AssetServer>();
+                let mut corpses = resources.get_mut::<Corpses>();
+                animation_system(&mut corpses.0, &asset_server, get_frame_time());
+                corpse_system(&mut corpses, &asset_server);
+            })
+            .run_if(in_state(&[GameState::Playing])),
+        );
+    if cfg!(debug_assertions) {
+        schedule
+            .add_system(
+                Stage::Update,
+                system("debug_colliders", |_world, resources| {
+                    // Dessine les boîtes de collision pour le débogage
+                    let debug = &mut resources.get_mut::<DebugWorld>().0;
+                    if debug_display_enabled(debug) {
+                        debug_draw_colliders_system(debug, &resources.get::<PhysicsResources>());
+                    }
+                })
+                .run_if(in_state(&[GameState::Playing])),
+            )
+            .add_system(
+                Stage::Update,
+                system("debug_flow_field", |_world, resources| {
+                    let debug = &mut resources.get_mut::<DebugWorld>().0;
+                    if debug_display_enabled(debug) {
+                        debug_draw_flow_field_system(debug, &resources.get::<FlowField>());
+                    }
+                })
+                .run_if(in_state(&[GameState::Playing])),
+            )
+            .add_system(
+                Stage::Update,
+                system("debug_infos", |_world, resources| {
+                    debug_infos_system(
+                        &mut resources.get_mut::<DebugWorld>().0,
+                        &resources.get::<GameTick>(),
+                    );
+                })
+                .run_if(in_state(&[GameState::Playing])),
+            );
+    }
+
+    schedule.add_system(
+        Stage::PostUpdate,
+        system("music", |_world, resources| {
+            let mut audio = resources.get_mut::<AudioManager>();
+            audio.play_music(music_for(*resources.get::<GameState>()));
+            audio.update(get_frame_time(), &resources.get::<AssetServer>());
+        }),
+    );
+
+    schedule
+        .add_system(
+            Stage::Render,
+            system("loading_screen", |_world, resources| {
+                draw_loading_screen(resources.get::<AssetServer>().required_progress());
+            })
+            .run_if(in_state(&[GameState::Loading])),
+        )
+        .add_system(
+            Stage::Render,
+            system("title_screen", |_world, resources| {
+                draw_title_screen(&resources.get::<InputMap>());
+            })
+            .run_if(in_state(&[GameState::Title])),
+        )
+        .add_system(
+            Stage::Render,
+            system("world", |world, resources| {
+                let mut tilemap = resources.try_get_mut::<Tilemap>();
+                draw_world(
+                    world,
+                    &resources.get::<Corpses>(),
+                    resources.try_get_mut::<DebugWorld>().as_deref_mut(),
+                    &resources.get::<AssetServer>(),
+                    &resources.get::<CameraController>(),
+                    tilemap.as_deref_mut(),
+                );
+            })
+            .run_if(in_state(IN_RUN)),
+        )
+        .add_system(
+            Stage::Render,
+            system("hud", |world, resources| {
+                draw_hud(
+                    world,
+                    &resources.get::<CameraController>(),
+                    &resources.get::<PhysicsResources>(),
+                );
+            })
+            .after("world")
+            .run_if(in_state(&[GameState::Playing])),
+        )
+        .add_system(
+            Stage::Render,
+            system("replay_hud", |_world, resources| {
+                let replay = resources.get::<ReplayControls>();
+                draw_replay_hud(
+                    &resources.get::<InputMap>(),
+                    replay.speed,
+                    replay.paused,
+                    replay.finished,
+                );
+            })
+            .after("hud")
+            .run_if(in_state(&[GameState::Playing]))
+            .run_if(resource_exists::<ReplayControls>),
+        )
+        .add_system(
+            Stage::Render,
+            system("pause_overlay", |_world, resources| {
+                draw_pause_overlay(
+                    &resources.get::<InputMap>(),
+                    &resources.get::<AudioManager>().settings,
+                    resources.get::<PauseMenu>().selected,
+                );
+            })
+            .after("world")
+            .run_if(in_state(&[GameState::Paused])),
+        )
+        .add_system(
+            Stage::Render,
+            system("controls_screen", |_world, resources| {
+                let controls = resources.get::<ControlsMenu>();
+                draw_controls_screen(
+                    &resources.get::<InputMap>(),
+                    controls.selected,
+                    controls.listening,
+                );
+            })
+            .after("world")
+            .run_if(in_state(&[GameState::Controls])),
+        )
+        .add_system(
+            Stage::Render,
+            system("level_up_overlay", |_world, resources| {
+                draw_level_up_overlay(
+                    &resources.get::<InputMap>(),
+                    &resources.get::<LevelUpChoices>().0,
+                );
+            })
+            .after("world")
+            .run_if(in_state(&[GameState::LevelUp])),
+        )
+        .add_system(
+            Stage::Render,
+            system("game_over_screen", |_world, resources| {
+                let game_tick = resources.get::<GameTick>();
+                let survived = game_tick.ticks_elapsed as f32 * game_tick.tick_rate;
+                draw_game_over_screen(&resources.get::<InputMap>(), survived);
+            })
+            .after("world")
+            .run_if(in_state(&[GameState::GameOver])),
+        );
+
+    schedule
+}
+
+/// Switch screens on input and play the run. Returns the state of the next frame.
+fn update_state(state: GameState, run: &mut Run, game: &Resources, options: &Options) -> GameState {
+    match state {
+        GameState::Loading => {
+            if !game.get::<AssetServer>().required_progress().is_done() {
+                GameState::Loading
+            } else if run.resources.contains::<ReplayControls>() {
+                // Replays skip the title screen
+                GameState::Playing
+            } else {
+                GameState::Title
+            }
+        }
+        GameState::Title => {
+            if game.get::<InputMap>().is_pressed(Action::Confirm) {
+                GameState::Playing
+            } else {
+                GameState::Title
+            }
+        }
+        GameState::Playing => {
+            let state = if let Some(mut replay) = run.resources.remove::<ReplayControls>() {
+                let state = replay.update(run, game);
+                run.resources.insert(replay);
+                state
+            } else {
+                let view_half_extents = game.get::<CameraController>().view_half_extents();
+                run.resources.insert(ViewHalfExtents(view_half_extents));
+                let (player_input, pause) = {
+                    let input = game.get::<InputMap>();
+                    (input.player_input(), input.is_pressed(Action::Pause))
+                };
+                let state = run.update(get_frame_time(), &player_input, game);
+                if state == GameState::Playing && pause {
+                    // Keep what was recorded so far, in case the game never comes back
+                    save_replay(run, options);
+                    GameState::Paused
+                } else {
+                    state
+                }
+            };
+            if state == GameState::GameOver {
+                save_replay(run, options);
+            }
+            state
+        }
+        GameState::Paused => {
+            let input = game.get::<InputMap>();
+            if input.is_pressed(Action::Pause) {
+                GameState::Playing
+            } else if input.is_pressed(Action::Controls) {
+                *game.get_mut::<ControlsMenu>() = ControlsMenu::default();
+                GameState::Controls
+            } else {
+                game.get_mut::<PauseMenu>()
+                    .update(&input, &mut game.get_mut::<AudioManager>());
+                GameState::Paused
+            }
+        }
+        GameState::Controls => {
+            let leave = game
+                .get_mut::<ControlsMenu>()
+                .update(&mut game.get_mut::<InputMap>());
+            if leave {
+                GameState::Paused
+            } else {
+                GameState::Controls
+            }
+        }
+        GameState::LevelUp => {
+            let choices = [Action::Choice1, Action::Choice2, Action::Choice3];
+            let index = {
+                let input = game.get::<InputMap>();
+                choices.iter().position(|action| input.is_pressed(*action))
+            };
+            match index {
+                Some(index) => run.choose_upgrade(index),
+                None => GameState::LevelUp,
+            }
+        }
+        GameState::GameOver => {
+            if game.get::<InputMap>().is_pressed(Action::Confirm) {
+                // Rebuild the whole run: world, physics and director
+                *run = new_run(options);
+                start_run(run, game);
+                GameState::Playing
+            } else {
+                GameState::GameOver
+            }
+        }
+    }
+}
+
+async fn game(options: Options) {
+    let data = GameData::load();
+    let floor = FloorDefinition::load(FLOOR_PATH);
+    let manifest = AssetManifest::load(MANIFEST_PATH)
+        .unwrap_or_else(|e| panic!("Loading asset manifest {}: {}", MANIFEST_PATH, e));
+    if let Err(errors) = manifest.validate(&asset_usages(&data, floor.as_ref())) {
+        for error in errors.iter() {
+            log::error!("Asset manifest: {}", error);
+        }
+        panic!(
+            "Invalid asset manifest {}: {} errors",
+            MANIFEST_PATH,
+            errors.len()
+        );
+    }
     let mut asset_server = AssetServer::new();
-    let mut enemy_spawner = EnemySpawner::default();
-    let mut game_tick = GameTick::default();
+    asset_server.queue_manifest(&manifest);
 
+    // The resources of the game, they outlive the runs
+    let mut game = Resources::default();
+    game.insert(asset_server);
+    game.insert(data);
+    if let Some(floor) = floor {
+        game.insert(floor);
+    }
+    game.insert(InputMap::load(&config_path(BINDINGS_FILE)));
+    game.insert(PauseMenu::default());
+    game.insert(ControlsMenu::default());
+    game.insert(CameraController::default());
+    game.insert(AudioManager::new(AudioSettings::load(&config_path(
+        SETTINGS_FILE,
+    ))));
+    game.insert(GameState::Loading);
     if cfg!(debug_assertions) {
         // Debug only
-        // Entity for debug lines
-        world.spawn((DebugLines(Vec::new()),));
-        world.spawn((DebugData::new(),));
+        game.insert(DebugWorld::new());
     }
 
-    asset_server
-        .load_assets(&["assets/player.png", "assets/enemy.png"])
-        .await;
+    let mut run = new_run(&options);
+    start_run(&mut run, &game);
 
-    spawn_player(&mut world);
+    let mut schedule = frame_schedule();
+    let mut watcher = cfg!(debug_assertions).then(|| AssetWatcher::new(WATCHED_DIR));
 
     loop {
         clear_background(GRAY);
-        physics_cleanup_system(&mut world, &mut physics_ressources);
-        
-        // Update physics
-        sync_physics_world(&mut world, &mut physics_ressources);
-        collision_register(&mut world, &physics_ressources);
+        schedule.run(
+            Stage::PreUpdate,
+            &mut run.world,
+            ResourceLayers::new(&run.resources, &game),
+        );
 
-        // Do things with entities
-        player_input_system(&mut world, &mut physics_ressources);
-        enemy_spawner_system(&mut world, &mut enemy_spawner);
-        enemy_ai_system(&mut world, &mut physics_ressources);
-
-        detect_player_dead(&mut world);
+        // Loading is async, outside of the schedule.
+        // Optional assets keep loading once the game started
+        if !game.get::<AssetServer>().progress().is_done() {
+            let mut asset_server = game.remove::<AssetServer>().expect("AssetServer resource");
+            asset_server.load_pending(LOAD_BUDGET).await;
+            game.insert(asset_server);
+        }
 
-        // Physics tick related
-        game_tick.accumulator += get_frame_time();
-        while game_tick.accumulator >= game_tick.tick_rate {
-            physics_step_system(&mut physics_ressources, &game_tick);
-            game_tick.accumulator -= game_tick.tick_rate;
-            game_tick.ticks_elapsed += 1;
+        if let Some(watcher) = watcher.as_mut() {
+            let changed = watcher.poll(get_time());
+            if !changed.is_empty() {
+                hot_reload(&changed, &manifest, &mut run, &mut game).await;
+            }
         }
 
-        sync_transforms(&mut world, &physics_ressources, &game_tick);
+        let state = *game.get::<GameState>();
+        let state = update_state(state, &mut run, &game, &options);
+        game.insert(state);
 
-        if cfg!(debug_assertions) {
-            // Debug only
-            // Dessine les boîtes de collision pour le débogage
-            debug_draw_colliders_system(&mut world, &physics_ressources);
-            debug_infos_system(&mut world, &game_tick);
-        }
+        let resources = ResourceLayers::new(&run.resources, &game);
+        schedule.run(Stage::Update, &mut run.world, resources);
+        schedule.run(Stage::PostUpdate, &mut run.world, resources);
+        schedule.run(Stage::Render, &mut run.world, resources);
 
-        draw_world(&mut world, &asset_server);
         // Send frame
         next_frame().await
     }
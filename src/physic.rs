@@ -6,7 +6,10 @@ use log::debug;
 use macroquad::prelude::vec2;
 use rapier2d::prelude::*;
 
-use crate::components::{Despawn, GameTick, Transform};
+use crate::{
+    collision::{CollisionMatrix, PhysicsLayer},
+    components::{Despawn, GameTick, Transform},
+};
 
 const GRAVITY: nalgebra::Matrix<f32, nalgebra::Const<2>, nalgebra::Const<1>, nalgebra::ArrayStorage<f32, 2, 1>> = vector![0.0, 0.0]; // Top-down, no gravity.
 
@@ -14,7 +17,6 @@ const GRAVITY: nalgebra::Matrix<f32, nalgebra::Const<2>, nalgebra::Const<1>, nal
 pub struct RigidBodyHandleComponent(pub RigidBodyHandle);
 
 /// A component to hold the handle to the Rapier collider.
-#[allow(dead_code)]
 pub struct ColliderHandleComponent(pub ColliderHandle);
 
 /// A component who list every entities who collide with
@@ -99,7 +101,12 @@ pub fn physics_step_system(physics: &mut PhysicsResources, game_tick: &GameTick)
 
 /// A system that finds entities with `RigidBody` and `Collider` components
 /// and adds them to the physics world.
-pub fn sync_physics_world(world: &mut World, physics: &mut PhysicsResources) {
+/// Colliders with a `PhysicsLayer` get the interactions of their layer in `collisions`.
+pub fn sync_physics_world(
+    world: &mut World,
+    physics: &mut PhysicsResources,
+    collisions: &CollisionMatrix,
+) {
     let mut commands = Vec::<(
         Entity,
         RigidBodyHandleComponent,
@@ -107,12 +114,15 @@ pub fn sync_physics_world(world: &mut World, physics: &mut PhysicsResources) {
         CollideWith,
     )>::new();
     // Query for entities that have a body and collider but no handle yet.
-    for (entity, (body, collider)) in world
-        .query_mut::<(&RigidBody, &mut Collider)>()
+    for (entity, (body, collider, layer)) in world
+        .query_mut::<(&RigidBody, &mut Collider, Option<&PhysicsLayer>)>()
         .without::<&RigidBodyHandleComponent>()
     {
         // Store the entity's bits in the collider's user_data field.
         collider.user_data = entity.to_bits().get() as u128;
+        if let Some(layer) = layer {
+            collisions.apply(*layer, collider);
+        }
         let body_handle = physics.rigid_body_set.insert(body.clone());
         let collider_handle = physics.collider_set.insert_with_parent(
             collider.clone(),
@@ -153,9 +163,6 @@ pub fn sync_transforms(world: &mut World, physics: &PhysicsResources, game_tick:
             transform.position = vec2(x, y);
             transform.rotation = body.rotation().angle();
         }
-
-        // Finally, remove the entity from the ECS
-        let _ = world.despawn(entity);
     }
 }
 
@@ -169,7 +176,14 @@ pub fn physics_cleanup_system(world: &mut World, physics: &mut PhysicsResources)
         entities_to_remove.push(entity);
     }
 
-    // 2. Clean up resources and despawn
+    // 2. Forget despawned entities in collision lists, rapier won't report these contacts as stopped
+    for (_entity, collide_with) in world.query_mut::<&mut CollideWith>() {
+        collide_with
+            .0
+            .retain(|other| !entities_to_remove.contains(other));
+    }
+
+    // 3. Clean up resources and despawn
     for entity in entities_to_remove {
         // Remove RigidBody from Rapier if it exists
         if let Ok(handle) = world.get::<&RigidBodyHandleComponent>(entity) {
@@ -190,6 +204,17 @@ pub fn physics_cleanup_system(world: &mut World, physics: &mut PhysicsResources)
     }
 }
 
+/// Get the world position of the center of an entity collider.
+pub fn collider_center(
+    physics: &PhysicsResources,
+    handle: &ColliderHandleComponent,
+) -> Option<macroquad::prelude::Vec2> {
+    physics
+        .collider_set
+        .get(handle.0)
+        .map(|collider| vec2(collider.translation().x, collider.translation().y))
+}
+
 /// Helper function to extract entities from a collision event.
 pub fn get_entities_from_collision(
     event: CollisionEvent,
@@ -1,11 +1,17 @@
-use hecs::World;
+use hecs::{EntityBuilder, World};
 use macroquad::prelude::*;
 use rapier2d::prelude::*;
 
 use crate::{
+    animation::Animator,
     asset_server::{self},
+    collision::PhysicsLayer,
     components::*,
+    damage::{HitCooldowns, Invulnerability},
+    input::PlayerInput,
     physic::{CollideWith, PhysicsResources, RigidBodyHandleComponent},
+    progression::{Experience, Level, PickupRadius, Stats},
+    weapon::{Weapon, WeaponKind, Weapons},
 };
 
 pub fn spawn_player(world: &mut World) {
@@ -15,65 +21,65 @@ pub fn spawn_player(world: &mut World) {
         .translation([32. / 2., 32. / 2.].into())
         .build();
 
-    // Get texture from AssetServer
-    world.spawn((
-        Player,
-        Transform {
-            position: vec2(0.0, 0.0),
-            ..Default::default()
-        },
-        Speed(200.),
-        Sprite {
-            asset_id: asset_server::assets::player(),
-            scale: 1.0,
-        },
-        player_body,
-        player_collider,
-        Health {
-            actual: 100.,
-            max: 100.,
-        },
-    ));
+    // Too many components for a single tuple, add them in bundles
+    let mut player = EntityBuilder::new();
+    player
+        // Get texture from AssetServer
+        .add_bundle((
+            Player,
+            Transform {
+                position: vec2(0.0, 0.0),
+                ..Default::default()
+            },
+            Speed(200.),
+            Sprite {
+                asset_id: asset_server::assets::PLAYER.id(),
+                scale: 1.0,
+                source: None,
+            },
+            Animator::new(Some("idle")),
+        ))
+        .add_bundle((PhysicsLayer::Player, player_body, player_collider))
+        .add_bundle((
+            Health {
+                actual: 100.,
+                max: 100.,
+            },
+            HitCooldowns::new(0.5),
+            Invulnerability::new(0.2),
+            Weapons(vec![Weapon::new(WeaponKind::Fireball)]),
+            Stats::default(),
+            Level(1),
+            Experience::default(),
+            PickupRadius(64.),
+        ));
+    world.spawn(player.build());
 }
 
-pub fn player_input_system(world: &mut World, physics: &mut PhysicsResources) {
+pub fn player_input_system(world: &mut World, physics: &mut PhysicsResources, input: &PlayerInput) {
     // Query for the player entity's rigid body handle.
     for (_id, (rigibody_handle, _player, speed)) in
         world.query_mut::<(&RigidBodyHandleComponent, &Player, &Speed)>()
     {
         // Get the rigid body from the physics world using the handle.
         if let Some(body) = physics.rigid_body_set.get_mut(rigibody_handle.0) {
-            let mut move_direction = vec2(0.0, 0.0);
-
-            if is_key_down(KeyCode::Z) {
-                move_direction.y -= 1.0;
-            }
-            if is_key_down(KeyCode::S) {
-                move_direction.y += 1.0;
-            }
-            if is_key_down(KeyCode::Q) {
-                move_direction.x -= 1.0;
-            }
-            if is_key_down(KeyCode::D) {
-                move_direction.x += 1.0;
-            }
-
-            // Set the linear velocity. Normalizing ensures consistent speed in all directions.
-            let desired_velocity = move_direction.normalize_or_zero() * speed.0;
+            // Set the linear velocity. Clamping keeps diagonals at full speed, and sticks analog.
+            let desired_velocity = input.movement.clamp_length_max(1.0) * speed.0;
             body.set_linvel([desired_velocity.x, desired_velocity.y].into(), true);
         }
     }
 }
 
-pub fn detect_player_dead(world: &mut World) {
+/// Returns `true` once the player has no health left.
+pub fn detect_player_dead(world: &mut World) -> bool {
     // Debug show all entity in CollideWith
     for (_id, collidewith) in world.query::<&CollideWith>().with::<&Player>().iter() {
         log::debug!("Entities who collide with : {:?}", collidewith.0);
     }
 
-    for (_id, health) in world.query::<&Health>().with::<&Player>().iter() {
-        if health.actual <= 0.0 {
-            println!("Player has died! Game Over.");
-        }
-    }
+    world
+        .query::<&Health>()
+        .with::<&Player>()
+        .iter()
+        .any(|(_id, health)| health.actual <= 0.0)
 }
@@ -0,0 +1,388 @@
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+use rapier2d::prelude::*;
+
+use crate::{
+    asset_server::{self},
+    collision::{PhysicsLayer, sensor_collider},
+    components::{
+        Despawn, Enemy, GameRng, Health, Player, Speed, Sprite, Tint, Transform, position_of,
+    },
+    events::{Events, Item, ItemPicked, PlayerLeveledUp},
+    physic::{
+        CollideWith, ColliderHandleComponent, PhysicsResources, RigidBodyHandleComponent,
+        collider_center,
+    },
+    spatial::SpatialIndex,
+    weapon::{MAX_WEAPON_LEVEL, Weapon, WeaponKind, Weapons},
+};
+
+/// Size in pixels of `gem.png`.
+const GEM_SIZE: f32 = 8.0;
+/// Speed of a gem attracted by the player. Faster than the player, so it always catches up.
+const GEM_MAGNET_SPEED: f32 = 400.0;
+/// Treasures are gems, bigger and golden.
+const TREASURE_SCALE: f32 = 3.0;
+/// Number of upgrades offered at each level-up.
+const UPGRADE_CHOICES: usize = 3;
+/// Maximum number of weapons the player can hold.
+const MAX_WEAPONS: usize = 4;
+
+/// An experience gem, dropped by killed enemies.
+pub struct XpGem {
+    pub value: u32,
+}
+
+/// A treasure, dropped by bosses. Opening it heals the player and grants `upgrades` upgrades.
+pub struct Treasure {
+    pub upgrades: u32,
+}
+
+/// Experience dropped by an enemy when killed.
+pub struct XpReward(pub u32);
+
+/// Distance under which gems are attracted to the player.
+pub struct PickupRadius(pub f32);
+
+/// A gem moving to the player.
+pub struct Attracted;
+
+pub struct Level(pub u32);
+
+pub struct Experience {
+    pub current: u32,
+    /// Experience needed to reach the next level.
+    pub to_next: u32,
+    /// Level-ups waiting for the player to pick an upgrade.
+    pub pending_level_ups: u32,
+}
+
+impl Default for Experience {
+    fn default() -> Self {
+        Self {
+            current: 0,
+            to_next: xp_to_next_level(1),
+            pending_level_ups: 0,
+        }
+    }
+}
+
+/// Multipliers improved by passive upgrades.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub damage_multiplier: f32,
+    pub cooldown_multiplier: f32,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            damage_multiplier: 1.0,
+            cooldown_multiplier: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassiveStat {
+    MaxHealth,
+    MoveSpeed,
+    PickupRadius,
+    Might,
+    Haste,
+}
+
+impl PassiveStat {
+    const ALL: [PassiveStat; 5] = [
+        PassiveStat::MaxHealth,
+        PassiveStat::MoveSpeed,
+        PassiveStat::PickupRadius,
+        PassiveStat::Might,
+        PassiveStat::Haste,
+    ];
+}
+
+/// An upgrade offered on level-up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upgrade {
+    NewWeapon(WeaponKind),
+    WeaponLevel(WeaponKind),
+    Passive(PassiveStat),
+}
+
+impl Upgrade {
+    pub fn description(&self) -> String {
+        match self {
+            Upgrade::NewWeapon(kind) => format!("New weapon: {}", kind.name()),
+            Upgrade::WeaponLevel(kind) => format!("{} level up", kind.name()),
+            Upgrade::Passive(PassiveStat::MaxHealth) => "Max health +20".to_owned(),
+            Upgrade::Passive(PassiveStat::MoveSpeed) => "Move speed +10%".to_owned(),
+            Upgrade::Passive(PassiveStat::PickupRadius) => "Pickup radius +25%".to_owned(),
+            Upgrade::Passive(PassiveStat::Might) => "Damage +10%".to_owned(),
+            Upgrade::Passive(PassiveStat::Haste) => "Cooldowns -8%".to_owned(),
+        }
+    }
+}
+
+/// Experience needed to go from `level` to the next one.
+pub fn xp_to_next_level(level: u32) -> u32 {
+    5 * level
+}
+
+/// Spawn an experience gem centered on `center`.
+fn spawn_gem(world: &mut World, center: Vec2, value: u32) {
+    let half_size = GEM_SIZE / 2.;
+    let position = center - vec2(half_size, half_size);
+
+    let gem_body = RigidBodyBuilder::kinematic_velocity_based()
+        .translation([position.x, position.y].into())
+        .build();
+    // Sensor: the player walks through gems to collect them.
+    let gem_collider = sensor_collider(ColliderBuilder::ball(half_size))
+        .translation([half_size, half_size].into())
+        .build();
+
+    world.spawn((
+        XpGem { value },
+        Transform {
+            position,
+            ..Default::default()
+        },
+        Sprite {
+            asset_id: asset_server::assets::GEM.id(),
+            scale: 1.0,
+            source: None,
+        },
+        PhysicsLayer::Pickup,
+        gem_body,
+        gem_collider,
+    ));
+}
+
+/// Spawn a treasure centered on `center`.
+pub fn spawn_treasure(world: &mut World, center: Vec2, upgrades: u32) {
+    let half_size = GEM_SIZE * TREASURE_SCALE / 2.;
+    let position = center - vec2(half_size, half_size);
+
+    let treasure_body = RigidBodyBuilder::fixed()
+        .translation([position.x, position.y].into())
+        .build();
+    let treasure_collider = sensor_collider(ColliderBuilder::ball(half_size))
+        .translation([half_size, half_size].into())
+        .build();
+
+    world.spawn((
+        Treasure { upgrades },
+        Transform {
+            position,
+            ..Default::default()
+        },
+        Sprite {
+            asset_id: asset_server::assets::GEM.id(),
+            scale: TREASURE_SCALE,
+            source: None,
+        },
+        Tint(GOLD),
+        PhysicsLayer::Pickup,
+        treasure_body,
+        treasure_collider,
+    ));
+}
+
+/// Drop an experience gem where each killed enemy was.
+pub fn drop_experience_system(world: &mut World, physics: &PhysicsResources) {
+    let drops: Vec<(Vec2, u32)> = world
+        .query::<(&Health, &XpReward, &ColliderHandleComponent)>()
+        .with::<(&Enemy, &Despawn)>()
+        .iter()
+        .filter(|(_id, (health, _reward, _handle))| health.actual <= 0.0)
+        .filter_map(|(_id, (_health, reward, handle))| {
+            collider_center(physics, handle).map(|center| (center, reward.0))
+        })
+        .collect();
+
+    for (center, value) in drops {
+        spawn_gem(world, center, value);
+    }
+}
+
+/// Attract gems within the player pickup radius, stop the ones it left.
+pub fn magnet_system(world: &mut World, physics: &mut PhysicsResources, index: &SpatialIndex) {
+    let in_range: Vec<(Entity, Vec2, Vec2)> = world
+        .query::<(&ColliderHandleComponent, &PickupRadius)>()
+        .with::<&Player>()
+        .iter()
+        .find_map(|(_id, (handle, radius))| {
+            collider_center(physics, handle).map(|center| (center, radius.0))
+        })
+        .map(|(player_center, radius)| {
+            index
+                .pickups
+                .query_radius(player_center, radius)
+                .map(|(gem, center)| (gem, center, player_center))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let left: Vec<Entity> = world
+        .query::<()>()
+        .with::<(&XpGem, &Attracted)>()
+        .iter()
+        .map(|(gem, ())| gem)
+        .filter(|gem| !in_range.iter().any(|(other, _, _)| other == gem))
+        .collect();
+    for gem in left {
+        set_gem_velocity(world, physics, gem, Vec2::ZERO);
+        let _ = world.remove_one::<Attracted>(gem);
+    }
+
+    for (gem, center, player_center) in in_range {
+        let velocity = (player_center - center).normalize_or_zero() * GEM_MAGNET_SPEED;
+        set_gem_velocity(world, physics, gem, velocity);
+        if !world.satisfies::<&Attracted>(gem).unwrap_or(true) {
+            let _ = world.insert_one(gem, Attracted);
+        }
+    }
+}
+
+fn set_gem_velocity(world: &World, physics: &mut PhysicsResources, gem: Entity, velocity: Vec2) {
+    let Ok(body_handle) = world.get::<&RigidBodyHandleComponent>(gem) else {
+        return;
+    };
+    if let Some(body) = physics.rigid_body_set.get_mut(body_handle.0) {
+        body.set_linvel([velocity.x, velocity.y].into(), true);
+    }
+}
+
+/// Collect the gems and treasures touching the player, level up and open treasures.
+pub fn collect_pickups_system(
+    world: &mut World,
+    item_picked: &mut Events<ItemPicked>,
+    player_leveled_up: &mut Events<PlayerLeveledUp>,
+) {
+    let mut collected: Vec<(Entity, Entity, Item)> = Vec::new();
+    for (player, collide_with) in world.query::<&CollideWith>().with::<&Player>().iter() {
+        for other in collide_with.0.iter() {
+            if world.satisfies::<&Despawn>(*other).unwrap_or(false) {
+                continue;
+            }
+            if let Ok(gem) = world.get::<&XpGem>(*other) {
+                collected.push((player, *other, Item::XpGem { value: gem.value }));
+            } else if let Ok(treasure) = world.get::<&Treasure>(*other) {
+                collected.push((
+                    player,
+                    *other,
+                    Item::Treasure {
+                        upgrades: treasure.upgrades,
+                    },
+                ));
+            }
+        }
+    }
+
+    for (player, pickup, item) in collected {
+        item_picked.send(ItemPicked {
+            item,
+            position: position_of(world, pickup),
+        });
+        let _ = world.insert_one(pickup, Despawn);
+
+        match item {
+            Item::XpGem { value } => {
+                let Ok((experience, level)) =
+                    world.query_one_mut::<(&mut Experience, &mut Level)>(player)
+                else {
+                    continue;
+                };
+                experience.current += value;
+                while experience.current >= experience.to_next {
+                    experience.current -= experience.to_next;
+                    level.0 += 1;
+                    experience.to_next = xp_to_next_level(level.0);
+                    experience.pending_level_ups += 1;
+                    log::info!("Player reached level {}", level.0);
+                    player_leveled_up.send(PlayerLeveledUp);
+                }
+            }
+            Item::Treasure { upgrades } => {
+                let Ok((experience, health)) =
+                    world.query_one_mut::<(&mut Experience, &mut Health)>(player)
+                else {
+                    continue;
+                };
+                // Upgrades without levels, picked like level-ups
+                experience.pending_level_ups += upgrades;
+                health.actual = health.max;
+                log::info!("Treasure opened: {} upgrades", upgrades);
+            }
+        }
+    }
+}
+
+/// Returns `true` if the player has an upgrade to pick.
+pub fn has_pending_level_up(world: &World) -> bool {
+    world
+        .query::<&Experience>()
+        .with::<&Player>()
+        .iter()
+        .any(|(_id, experience)| experience.pending_level_ups > 0)
+}
+
+/// Pick a random set of upgrades available to the player.
+pub fn roll_upgrades(world: &World, rng: &mut GameRng) -> Vec<Upgrade> {
+    let mut pool = Vec::new();
+
+    if let Some((_id, weapons)) = world.query::<&Weapons>().with::<&Player>().iter().next() {
+        for weapon in weapons.0.iter() {
+            if weapon.level < MAX_WEAPON_LEVEL {
+                pool.push(Upgrade::WeaponLevel(weapon.kind));
+            }
+        }
+        if weapons.0.len() < MAX_WEAPONS {
+            for kind in WeaponKind::ALL {
+                if !weapons.0.iter().any(|weapon| weapon.kind == kind) {
+                    pool.push(Upgrade::NewWeapon(kind));
+                }
+            }
+        }
+    }
+
+    pool.extend(PassiveStat::ALL.map(Upgrade::Passive));
+
+    rng.choose_multiple(&pool, UPGRADE_CHOICES)
+}
+
+/// Apply the chosen upgrade to the player, and consume one pending level-up.
+pub fn apply_upgrade(world: &mut World, upgrade: Upgrade) {
+    for (_id, (experience, weapons, health, speed, radius, stats)) in world
+        .query_mut::<(
+            &mut Experience,
+            &mut Weapons,
+            &mut Health,
+            &mut Speed,
+            &mut PickupRadius,
+            &mut Stats,
+        )>()
+        .with::<&Player>()
+    {
+        experience.pending_level_ups = experience.pending_level_ups.saturating_sub(1);
+
+        match upgrade {
+            Upgrade::NewWeapon(kind) => weapons.0.push(Weapon::new(kind)),
+            Upgrade::WeaponLevel(kind) => {
+                if let Some(weapon) = weapons.0.iter_mut().find(|weapon| weapon.kind == kind) {
+                    weapon.level_up();
+                }
+            }
+            Upgrade::Passive(PassiveStat::MaxHealth) => {
+                health.max += 20.0;
+                health.actual += 20.0;
+            }
+            Upgrade::Passive(PassiveStat::MoveSpeed) => speed.0 *= 1.1,
+            Upgrade::Passive(PassiveStat::PickupRadius) => radius.0 *= 1.25,
+            Upgrade::Passive(PassiveStat::Might) => stats.damage_multiplier += 0.1,
+            Upgrade::Passive(PassiveStat::Haste) => stats.cooldown_multiplier *= 0.92,
+        }
+        log::info!("Upgrade applied: {}", upgrade.description());
+    }
+}
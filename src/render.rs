@@ -2,43 +2,31 @@ use hecs::World;
 use macroquad::prelude::*;
 
 use crate::{
+    animation::Corpses,
     asset_server::AssetServer,
-    components::{Sprite, Text, Transform},
-    debug::debug_draw,
+    camera::CameraController,
+    components::{Health, Player, Sprite, Text, Tint, Transform},
+    debug::{DebugWorld, debug_draw},
+    enemy::{AiState, ChargePhase},
+    tilemap::Tilemap,
 };
 
-pub fn draw_world(world: &mut World, asset_server: &AssetServer) {
-    let zoom_level = 0.0025;
-    let aspect_ratio = screen_width() / screen_height();
-
-    let camera = Camera2D {
-        zoom: vec2(zoom_level, zoom_level * aspect_ratio),
-        ..Default::default()
-    };
-
-    set_camera(&camera);
-
-    // Viewport for AABB Culling
-
-    let min_screen = vec2(0.0, 0.0);
-    let max_screen = vec2(screen_width(), screen_height());
-
-    let min_world = camera.screen_to_world(min_screen);
-    let max_world = camera.screen_to_world(max_screen);
-
-    // Camera rendering cube
-
-    let view_rect = Rect::new(
-        min_world.x.min(max_world.x),
-        min_world.y.min(max_world.y),
-        (max_world.x - min_world.x).abs(),
-        (max_world.y - min_world.y).abs(),
-    );
+/// Size of `sprite` on screen, in pixels.
+fn sprite_size(asset_server: &AssetServer, sprite: &Sprite, transform: &Transform) -> Vec2 {
+    let size = sprite
+        .source
+        .map(|source| source.size())
+        .unwrap_or_else(|| asset_server.get_texture(sprite.asset_id).size());
+    size * sprite.scale * transform.scale
+}
 
-    for (_id, (transform, sprite)) in &mut world.query::<(&Transform, &Sprite)>() {
+/// Draw the sprites of `world` in the view.
+fn draw_sprites(world: &World, asset_server: &AssetServer, view_rect: Rect) {
+    for (_id, (transform, sprite, tint)) in
+        &mut world.query::<(&Transform, &Sprite, Option<&Tint>)>()
+    {
         let texture = asset_server.get_texture(sprite.asset_id);
-        let w = texture.width() * sprite.scale * transform.scale.x;
-        let h = texture.height() * sprite.scale * transform.scale.y;
+        let Vec2 { x: w, y: h } = sprite_size(asset_server, sprite, transform);
 
         let sprite_rect = Rect::new(transform.position.x, transform.position.y, w, h);
 
@@ -50,17 +38,71 @@ pub fn draw_world(world: &mut World, asset_server: &AssetServer) {
             texture,
             transform.position.x,
             transform.position.y,
-            WHITE,
+            tint.map_or(WHITE, |tint| tint.0),
             DrawTextureParams {
                 dest_size: Some(vec2(
                     w,
                     h,
                 )),
+                source: sprite.source,
                 rotation: transform.rotation,
                 ..Default::default()
             },
         )
     }
+}
+
+pub fn draw_world(
+    world: &World,
+    corpses: &Corpses,
+    debug: Option<&mut DebugWorld>,
+    asset_server: &AssetServer,
+    camera: &CameraController,
+    tilemap: Option<&mut Tilemap>,
+) {
+    set_camera(&camera.camera_2d());
+
+    // Viewport for AABB Culling
+    let view_rect = camera.view_rect();
+
+    // Floor, under everything else
+    if let Some(tilemap) = tilemap {
+        tilemap.draw(asset_server, view_rect);
+    }
+
+    // The dead under the living
+    draw_sprites(&corpses.0, asset_server, view_rect);
+    draw_sprites(world, asset_server, view_rect);
+
+    // Charge telegraphs, along the coming dash
+    for (_id, (transform, sprite, state)) in &mut world.query::<(&Transform, &Sprite, &AiState)>() {
+        let ChargePhase::Telegraph { direction, length } = state.charge else {
+            continue;
+        };
+        let center = transform.position + sprite_size(asset_server, sprite, transform) / 2.0;
+        let end = center + direction * length;
+        draw_line(
+            center.x,
+            center.y,
+            end.x,
+            end.y,
+            6.0,
+            Color::new(1.0, 0.0, 0.0, 0.35),
+        );
+    }
+
+    // Player health bar, above the sprite
+    for (_id, (transform, sprite, health)) in world
+        .query::<(&Transform, &Sprite, &Health)>()
+        .with::<&Player>()
+        .iter()
+    {
+        let ratio = (health.actual / health.max).clamp(0.0, 1.0);
+        let width = sprite_size(asset_server, sprite, transform).x;
+        let (x, y) = (transform.position.x, transform.position.y - 6.0);
+        draw_rectangle(x, y, width, 4.0, DARKGRAY);
+        draw_rectangle(x, y, width * ratio, 4.0, RED);
+    }
 
     for (_id, (pos, text)) in &mut world.query::<(&Transform, &Text)>() {
         draw_text_ex(
@@ -76,6 +118,8 @@ pub fn draw_world(world: &mut World, asset_server: &AssetServer) {
 
     if cfg!(debug_assertions) {
         draw_rectangle_lines(view_rect.x, view_rect.y, view_rect.w, view_rect.h, 0.1, RED);
-        debug_draw(world);
+        if let Some(debug) = debug {
+            debug_draw(&mut debug.0);
+        }
     }
 }
@@ -0,0 +1,532 @@
+//! Record a run to a compact replay file, and play it back through the same systems.
+//!
+//! File layout, little endian:
+//! - header: `VSRP` magic, version `u8`, seed `u64`
+//! - records, in tick order, each starting with a tag byte:
+//!   - `1` input: `u16` tick count, `i8` x, `i8` y. The same input is repeated for `count` ticks.
+//!   - `2` upgrade choice: `u8` index in the offered choices.
+//!   - `3` state hash: `u64`, computed after the last played tick.
+//!   - `4` view: `f32` half width, `f32` half height. Spawns depend on it.
+use std::fmt;
+
+use macroquad::prelude::*;
+
+use crate::{
+    input::PlayerInput,
+    simulation::{Run, ViewHalfExtents},
+};
+
+const MAGIC: &[u8; 4] = b"VSRP";
+/// Format and simulation version. Bumped with every change to the simulation, older replays
+/// would play differently and desync.
+const VERSION: u8 = 8;
+
+/// A state hash is recorded every `HASH_INTERVAL` ticks, two seconds at the default tick rate.
+pub const HASH_INTERVAL: u32 = 64;
+
+const TAG_INPUT: u8 = 1;
+const TAG_CHOICE: u8 = 2;
+const TAG_HASH: u8 = 3;
+const TAG_VIEW: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Record {
+    Input { count: u16, movement: [i8; 2] },
+    Choice(u8),
+    Hash(u64),
+    View(Vec2),
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    /// The file is not a replay, or is truncated.
+    Format(String),
+    /// The replayed run doesn't match the recorded one anymore.
+    Desync {
+        tick: u32,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "{}", e),
+            ReplayError::Format(e) => write!(f, "invalid replay: {}", e),
+            ReplayError::Desync {
+                tick,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "replay desync at tick {}: expected state hash {:016x}, got {:016x}",
+                tick, expected, actual
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(e: std::io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// Record the inputs of a live run.
+pub struct ReplayRecorder {
+    seed: u64,
+    records: Vec<Record>,
+    /// Input being repeated, not written in `records` yet.
+    pending: Option<([i8; 2], u16)>,
+    last_view: Option<Vec2>,
+}
+
+impl ReplayRecorder {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            records: Vec::new(),
+            pending: None,
+            last_view: None,
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some((movement, count)) = self.pending.take() {
+            self.records.push(Record::Input { count, movement });
+        }
+    }
+
+    /// Record the camera view, only when it changed since the last tick.
+    pub fn record_view(&mut self, view_half_extents: Vec2) {
+        if self.last_view != Some(view_half_extents) {
+            self.flush();
+            self.records.push(Record::View(view_half_extents));
+            self.last_view = Some(view_half_extents);
+        }
+    }
+
+    pub fn record_tick(&mut self, input: &PlayerInput) {
+        let movement = input.to_bytes();
+        match self.pending.as_mut() {
+            Some((pending, count)) if *pending == movement && *count < u16::MAX => *count += 1,
+            _ => {
+                self.flush();
+                self.pending = Some((movement, 1));
+            }
+        }
+    }
+
+    pub fn record_choice(&mut self, index: usize) {
+        self.flush();
+        self.records.push(Record::Choice(index as u8));
+    }
+
+    pub fn record_hash(&mut self, hash: u64) {
+        self.flush();
+        self.records.push(Record::Hash(hash));
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+
+        let pending = self
+            .pending
+            .map(|(movement, count)| Record::Input { count, movement });
+        for record in self.records.iter().chain(pending.iter()) {
+            match record {
+                Record::Input { count, movement } => {
+                    bytes.push(TAG_INPUT);
+                    bytes.extend_from_slice(&count.to_le_bytes());
+                    bytes.extend(movement.map(|axis| axis as u8));
+                }
+                Record::Choice(index) => {
+                    bytes.push(TAG_CHOICE);
+                    bytes.push(*index);
+                }
+                Record::Hash(hash) => {
+                    bytes.push(TAG_HASH);
+                    bytes.extend_from_slice(&hash.to_le_bytes());
+                }
+                Record::View(view) => {
+                    bytes.push(TAG_VIEW);
+                    bytes.extend_from_slice(&view.x.to_le_bytes());
+                    bytes.extend_from_slice(&view.y.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+/// What the replay asks for next.
+#[derive(Debug, Clone, Copy)]
+pub enum ReplayStep {
+    /// Play one tick with this input.
+    Tick(PlayerInput),
+    /// Pick the upgrade at this index.
+    Choice(usize),
+    End,
+}
+
+/// Play a recorded run back.
+pub struct ReplayPlayer {
+    seed: u64,
+    records: Vec<Record>,
+    position: usize,
+    /// Input of the current record, and the ticks left to play it.
+    current: ([i8; 2], u16),
+}
+
+/// Read `N` bytes at `*cursor`, and move the cursor after them.
+fn take<const N: usize>(bytes: &[u8], cursor: &mut usize) -> Result<[u8; N], ReplayError> {
+    let chunk = bytes
+        .get(*cursor..*cursor + N)
+        .ok_or_else(|| ReplayError::Format(format!("truncated at byte {}", cursor)))?;
+    *cursor += N;
+    Ok(chunk.try_into().expect("chunk has N bytes"))
+}
+
+impl ReplayPlayer {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut cursor = 0;
+        if &take::<4>(bytes, &mut cursor)? != MAGIC {
+            return Err(ReplayError::Format("bad magic".to_owned()));
+        }
+        let [version] = take::<1>(bytes, &mut cursor)?;
+        if version != VERSION {
+            return Err(ReplayError::Format(format!(
+                "unsupported version {}, expected {}",
+                version, VERSION
+            )));
+        }
+        let seed = u64::from_le_bytes(take(bytes, &mut cursor)?);
+
+        let mut records = Vec::new();
+        while cursor < bytes.len() {
+            let [tag] = take::<1>(bytes, &mut cursor)?;
+            let record = match tag {
+                TAG_INPUT => {
+                    let count = u16::from_le_bytes(take(bytes, &mut cursor)?);
+                    let movement = take::<2>(bytes, &mut cursor)?.map(|axis| axis as i8);
+                    Record::Input { count, movement }
+                }
+                TAG_CHOICE => Record::Choice(take::<1>(bytes, &mut cursor)?[0]),
+                TAG_HASH => Record::Hash(u64::from_le_bytes(take(bytes, &mut cursor)?)),
+                TAG_VIEW => {
+                    let x = f32::from_le_bytes(take(bytes, &mut cursor)?);
+                    let y = f32::from_le_bytes(take(bytes, &mut cursor)?);
+                    Record::View(vec2(x, y))
+                }
+                _ => {
+                    return Err(ReplayError::Format(format!(
+                        "unknown record {} at byte {}",
+                        tag,
+                        cursor - 1
+                    )));
+                }
+            };
+            records.push(record);
+        }
+
+        Ok(Self {
+            seed,
+            records,
+            position: 0,
+            current: ([0, 0], 0),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self, ReplayError> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Get the next step to play on `run`.
+    /// Checks the recorded state hashes, and fails on the first mismatch.
+    pub fn next_step(&mut self, run: &mut Run) -> Result<ReplayStep, ReplayError> {
+        loop {
+            let (movement, remaining) = &mut self.current;
+            if *remaining > 0 {
+                *remaining -= 1;
+                return Ok(ReplayStep::Tick(PlayerInput::from_bytes(*movement)));
+            }
+
+            let Some(record) = self.records.get(self.position).copied() else {
+                return Ok(ReplayStep::End);
+            };
+            self.position += 1;
+
+            match record {
+                Record::Input { count, movement } => self.current = (movement, count),
+                Record::Choice(index) => return Ok(ReplayStep::Choice(index as usize)),
+                Record::View(view) => run.resources.insert(ViewHalfExtents(view)),
+                Record::Hash(expected) => {
+                    let actual = run.state_hash();
+                    if actual != expected {
+                        return Err(ReplayError::Desync {
+                            tick: run.ticks_elapsed(),
+                            expected,
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// FNV-1a, stable across builds and platforms unlike `DefaultHasher`.
+pub struct StateHasher(u64);
+
+impl StateHasher {
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write(&value.to_bits().to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{schedule::Resources, simulation::GameData, state::GameState};
+
+    fn input(x: f32, y: f32) -> PlayerInput {
+        PlayerInput {
+            movement: vec2(x, y),
+        }
+        .quantized()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut recorder = ReplayRecorder::new(0xdead_beef_cafe);
+        recorder.record_view(vec2(400.0, 225.0));
+        for _ in 0..3 {
+            recorder.record_tick(&input(1.0, 0.0));
+        }
+        recorder.record_choice(2);
+        recorder.record_tick(&input(-0.5, 0.75));
+        recorder.record_hash(0x0123_4567_89ab_cdef);
+        recorder.record_view(vec2(640.0, 360.0));
+        // Still pending when saved
+        recorder.record_tick(&input(0.0, -1.0));
+
+        let player = ReplayPlayer::parse(&recorder.to_bytes()).unwrap();
+        assert_eq!(player.seed(), 0xdead_beef_cafe);
+        assert_eq!(
+            player.records,
+            vec![
+                Record::View(vec2(400.0, 225.0)),
+                Record::Input {
+                    count: 3,
+                    movement: [127, 0]
+                },
+                Record::Choice(2),
+                Record::Input {
+                    count: 1,
+                    movement: [-64, 95]
+                },
+                Record::Hash(0x0123_4567_89ab_cdef),
+                Record::View(vec2(640.0, 360.0)),
+                Record::Input {
+                    count: 1,
+                    movement: [0, -127]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn long_inputs_split_at_the_count_limit() {
+        let mut recorder = ReplayRecorder::new(0);
+        for _ in 0..u16::MAX as u32 + 5 {
+            recorder.record_tick(&input(0.0, 1.0));
+        }
+
+        let player = ReplayPlayer::parse(&recorder.to_bytes()).unwrap();
+        assert_eq!(
+            player.records,
+            vec![
+                Record::Input {
+                    count: u16::MAX,
+                    movement: [0, 127]
+                },
+                Record::Input {
+                    count: 5,
+                    movement: [0, 127]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let bytes = ReplayRecorder::new(7).to_bytes();
+        assert!(matches!(
+            ReplayPlayer::parse(b"RIFF"),
+            Err(ReplayError::Format(_))
+        ));
+
+        for version in [VERSION - 1, VERSION + 1] {
+            let mut other_version = bytes.clone();
+            other_version[4] = version;
+            assert!(matches!(
+                ReplayPlayer::parse(&other_version),
+                Err(ReplayError::Format(e)) if e.starts_with("unsupported version")
+            ));
+        }
+
+        let mut truncated = bytes.clone();
+        truncated.extend_from_slice(&[TAG_INPUT, 1]);
+        assert!(matches!(
+            ReplayPlayer::parse(&truncated),
+            Err(ReplayError::Format(_))
+        ));
+
+        let mut unknown = bytes;
+        unknown.push(0xff);
+        assert!(matches!(
+            ReplayPlayer::parse(&unknown),
+            Err(ReplayError::Format(_))
+        ));
+    }
+
+    /// Play `ticks` ticks with a fixed script, taking the first upgrade on level-up.
+    fn play_scripted(run: &mut Run, game: &Resources, ticks: u32) {
+        for tick in 0..ticks {
+            let angle = (tick / 96) as f32;
+            let mut state = run.tick(&input(angle.cos(), angle.sin()), game);
+            while state == GameState::LevelUp {
+                state = run.choose_upgrade(0);
+            }
+            if state == GameState::GameOver {
+                break;
+            }
+        }
+    }
+
+    fn game() -> Resources {
+        let mut game = Resources::default();
+        game.insert(GameData::load());
+        game
+    }
+
+    /// Record a scripted run of `seed`. `setup` changes the run before it starts.
+    fn record(game: &Resources, seed: u64, setup: impl FnOnce(&mut Run)) -> Run {
+        let mut recorded = Run::new(seed);
+        recorded.recorder = Some(ReplayRecorder::new(seed));
+        setup(&mut recorded);
+        play_scripted(&mut recorded, game, HASH_INTERVAL * 20);
+        recorded
+    }
+
+    /// Play `player` to its end on a new run.
+    fn replay(game: &Resources, mut player: ReplayPlayer) -> Result<Run, ReplayError> {
+        let mut replayed = Run::new(player.seed());
+        loop {
+            match player.next_step(&mut replayed)? {
+                ReplayStep::Tick(input) => {
+                    replayed.tick(&input, game);
+                }
+                ReplayStep::Choice(index) => {
+                    replayed.choose_upgrade(index);
+                }
+                ReplayStep::End => return Ok(replayed),
+            }
+        }
+    }
+
+    #[test]
+    fn recorded_run_replays_without_desync() {
+        let game = game();
+        let recorded = record(&game, 42, |_run| {});
+        let bytes = recorded.recorder.as_ref().unwrap().to_bytes();
+
+        let replayed = replay(&game, ReplayPlayer::parse(&bytes).unwrap()).unwrap();
+        assert_eq!(replayed.ticks_elapsed(), recorded.ticks_elapsed());
+        assert_eq!(replayed.state_hash(), recorded.state_hash());
+    }
+
+    #[test]
+    fn entities_outside_the_simulation_keep_the_hash() {
+        let game = game();
+        let recorded = record(&game, 42, |run| {
+            run.world.spawn(());
+            run.world.spawn(());
+        });
+        let bytes = recorded.recorder.as_ref().unwrap().to_bytes();
+
+        let replayed = replay(&game, ReplayPlayer::parse(&bytes).unwrap()).unwrap();
+        assert_eq!(replayed.state_hash(), recorded.state_hash());
+    }
+
+    #[test]
+    fn reports_a_changed_state_hash() {
+        let game = game();
+        let recorded = record(&game, 42, |_run| {});
+        let bytes = recorded.recorder.as_ref().unwrap().to_bytes();
+
+        let mut player = ReplayPlayer::parse(&bytes).unwrap();
+        let second_hash = player
+            .records
+            .iter_mut()
+            .filter_map(|record| match record {
+                Record::Hash(hash) => Some(hash),
+                _ => None,
+            })
+            .nth(1)
+            .unwrap();
+        *second_hash ^= 1;
+
+        assert!(matches!(
+            replay(&game, player),
+            Err(ReplayError::Desync { tick, .. }) if tick == HASH_INTERVAL * 2
+        ));
+    }
+
+    #[test]
+    fn reports_a_changed_seed() {
+        let game = game();
+        let recorded = record(&game, 42, |_run| {});
+        let mut bytes = recorded.recorder.as_ref().unwrap().to_bytes();
+        // The seed follows the magic and the version
+        bytes[5..13].copy_from_slice(&43u64.to_le_bytes());
+
+        assert!(matches!(
+            replay(&game, ReplayPlayer::parse(&bytes).unwrap()),
+            Err(ReplayError::Desync { tick, .. }) if tick == HASH_INTERVAL
+        ));
+    }
+}
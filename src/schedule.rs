@@ -0,0 +1,355 @@
+//! Systems registered in ordered stages, and the resources they share.
+//!
+//! A system is a function of the `World` and the resources, it fetches what it needs.
+//! Resources live in two containers: those of the game, and those of the current run.
+//! Systems of a stage run in registration order, unless `after` says otherwise,
+//! and only when their run conditions hold.
+use std::{
+    any::{Any, TypeId, type_name},
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+};
+
+use hecs::World;
+
+use crate::state::GameState;
+
+/// When systems run. Every stage runs once per frame, except `FixedUpdate` which runs once
+/// per simulation tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Read the input devices.
+    PreUpdate,
+    /// Gameplay, on the fixed `GameTick`.
+    FixedUpdate,
+    /// Presentation that follows the simulation: camera, animations, sounds.
+    Update,
+    /// After the presentation of the frame, such as the music.
+    PostUpdate,
+    /// Draw the frame, the world first then the overlays.
+    Render,
+}
+
+/// Typed values shared by the systems, one per type.
+/// Borrows are checked at runtime, so a system can hold several resources at once.
+#[derive(Default)]
+pub struct Resources {
+    values: HashMap<TypeId, RefCell<Box<dyn Any>>>,
+}
+
+impl Resources {
+    /// Add `value`, replacing the resource of the same type.
+    pub fn insert<T: 'static>(&mut self, value: T) {
+        self.values
+            .insert(TypeId::of::<T>(), RefCell::new(Box::new(value)));
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.into_inner().downcast::<T>().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn try_get<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        self.values.get(&TypeId::of::<T>()).map(|value| {
+            Ref::map(value.borrow(), |value| {
+                value
+                    .downcast_ref::<T>()
+                    .expect("resource stored under its type")
+            })
+        })
+    }
+
+    pub fn try_get_mut<T: 'static>(&self) -> Option<RefMut<'_, T>> {
+        self.values.get(&TypeId::of::<T>()).map(|value| {
+            RefMut::map(value.borrow_mut(), |value| {
+                value
+                    .downcast_mut::<T>()
+                    .expect("resource stored under its type")
+            })
+        })
+    }
+
+    /// The resource of type `T`. Panics if there is none, a system can't run without it.
+    pub fn get<T: 'static>(&self) -> Ref<'_, T> {
+        self.try_get()
+            .unwrap_or_else(|| panic!("Missing resource {}", type_name::<T>()))
+    }
+
+    pub fn get_mut<T: 'static>(&self) -> RefMut<'_, T> {
+        self.try_get_mut()
+            .unwrap_or_else(|| panic!("Missing resource {}", type_name::<T>()))
+    }
+}
+
+/// The resources systems see: those of the run, then those of the game.
+/// A new run starts with new run resources, nothing carries over from the previous one.
+#[derive(Clone, Copy)]
+pub struct ResourceLayers<'a> {
+    run: &'a Resources,
+    game: &'a Resources,
+}
+
+impl<'a> ResourceLayers<'a> {
+    pub fn new(run: &'a Resources, game: &'a Resources) -> Self {
+        Self { run, game }
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.run.contains::<T>() || self.game.contains::<T>()
+    }
+
+    pub fn try_get<T: 'static>(&self) -> Option<Ref<'a, T>> {
+        self.run.try_get().or_else(|| self.game.try_get())
+    }
+
+    pub fn try_get_mut<T: 'static>(&self) -> Option<RefMut<'a, T>> {
+        self.run.try_get_mut().or_else(|| self.game.try_get_mut())
+    }
+
+    /// The resource of type `T`. Panics if there is none, a system can't run without it.
+    pub fn get<T: 'static>(&self) -> Ref<'a, T> {
+        self.try_get()
+            .unwrap_or_else(|| panic!("Missing resource {}", type_name::<T>()))
+    }
+
+    pub fn get_mut<T: 'static>(&self) -> RefMut<'a, T> {
+        self.try_get_mut()
+            .unwrap_or_else(|| panic!("Missing resource {}", type_name::<T>()))
+    }
+}
+
+type SystemFn = Box<dyn FnMut(&mut World, &ResourceLayers)>;
+type RunCondition = Box<dyn Fn(&ResourceLayers) -> bool>;
+
+/// A system and where it runs, built with `system`.
+pub struct SystemConfig {
+    name: &'static str,
+    run: SystemFn,
+    after: Vec<&'static str>,
+    conditions: Vec<RunCondition>,
+}
+
+/// Wrap `run` as a system. Names are used for ordering and must be unique in a stage.
+pub fn system(
+    name: &'static str,
+    run: impl FnMut(&mut World, &ResourceLayers) + 'static,
+) -> SystemConfig {
+    SystemConfig {
+        name,
+        run: Box::new(run),
+        after: Vec::new(),
+        conditions: Vec::new(),
+    }
+}
+
+impl SystemConfig {
+    /// Run after the system named `name`, in the same stage.
+    pub fn after(mut self, name: &'static str) -> Self {
+        self.after.push(name);
+        self
+    }
+
+    /// Only run when `condition` holds. Every condition must hold.
+    pub fn run_if(mut self, condition: impl Fn(&ResourceLayers) -> bool + 'static) -> Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+}
+
+/// Run condition: the `GameState` resource is one of `states`.
+pub fn in_state(states: &'static [GameState]) -> impl Fn(&ResourceLayers) -> bool {
+    move |resources| {
+        resources
+            .try_get::<GameState>()
+            .is_some_and(|state| states.contains(&state))
+    }
+}
+
+/// Run condition: there is a resource of type `T`.
+pub fn resource_exists<T: 'static>(resources: &ResourceLayers) -> bool {
+    resources.contains::<T>()
+}
+
+#[derive(Default)]
+struct StageSystems {
+    systems: Vec<SystemConfig>,
+    /// Systems were added since the last sort.
+    dirty: bool,
+}
+
+impl StageSystems {
+    /// Order the systems by their constraints, keeping the registration order otherwise.
+    /// Panics on an unknown name or a cycle, both are mistakes in the registration code.
+    fn sort(&mut self, stage: Stage) {
+        let systems = std::mem::take(&mut self.systems);
+        let index_of = |name: &str| {
+            systems
+                .iter()
+                .position(|system| system.name == name)
+                .unwrap_or_else(|| panic!("{:?}: no system named {}", stage, name))
+        };
+
+        // Edges from each system to the ones that must run after it
+        let mut successors = vec![Vec::new(); systems.len()];
+        let mut predecessors = vec![0; systems.len()];
+        for (index, system) in systems.iter().enumerate() {
+            for name in system.after.iter() {
+                successors[index_of(name)].push(index);
+                predecessors[index] += 1;
+            }
+        }
+
+        let mut order = Vec::with_capacity(systems.len());
+        let mut done = vec![false; systems.len()];
+        while order.len() < systems.len() {
+            // The first registered system that is free to run
+            let Some(next) = (0..systems.len()).find(|&i| !done[i] && predecessors[i] == 0) else {
+                let cycle: Vec<_> = (0..systems.len())
+                    .filter(|&i| !done[i])
+                    .map(|i| systems[i].name)
+                    .collect();
+                panic!("{:?}: ordering cycle between {:?}", stage, cycle);
+            };
+            done[next] = true;
+            order.push(next);
+            for &successor in successors[next].iter() {
+                predecessors[successor] -= 1;
+            }
+        }
+
+        let mut systems: Vec<Option<SystemConfig>> = systems.into_iter().map(Some).collect();
+        self.systems = order
+            .into_iter()
+            .filter_map(|index| systems[index].take())
+            .collect();
+        self.dirty = false;
+    }
+}
+
+/// Systems of every stage.
+#[derive(Default)]
+pub struct Schedule {
+    stages: HashMap<Stage, StageSystems>,
+}
+
+impl Schedule {
+    pub fn add_system(&mut self, stage: Stage, system: SystemConfig) -> &mut Self {
+        let stage_systems = self.stages.entry(stage).or_default();
+        if stage_systems
+            .systems
+            .iter()
+            .any(|other| other.name == system.name)
+        {
+            panic!("{:?}: two systems named {}", stage, system.name);
+        }
+        stage_systems.systems.push(system);
+        stage_systems.dirty = true;
+        self
+    }
+
+    /// Run the systems of `stage` whose conditions hold, in order.
+    pub fn run(&mut self, stage: Stage, world: &mut World, resources: ResourceLayers) {
+        let Some(stage_systems) = self.stages.get_mut(&stage) else {
+            return;
+        };
+        if stage_systems.dirty {
+            stage_systems.sort(stage);
+        }
+        for system in stage_systems.systems.iter_mut() {
+            if system
+                .conditions
+                .iter()
+                .all(|condition| condition(&resources))
+            {
+                (system.run)(world, &resources);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Systems that log their name, run once in `Stage::Update`. Returns the names in run order.
+    fn run_order(systems: Vec<SystemConfig>) -> Vec<&'static str> {
+        let mut schedule = Schedule::default();
+        for system in systems {
+            schedule.add_system(Stage::Update, system);
+        }
+        let mut run = Resources::default();
+        run.insert(Vec::<&'static str>::new());
+        schedule.run(
+            Stage::Update,
+            &mut World::new(),
+            ResourceLayers::new(&run, &Resources::default()),
+        );
+        run.remove::<Vec<&'static str>>().unwrap()
+    }
+
+    fn logger(name: &'static str) -> SystemConfig {
+        system(name, move |_world, resources| {
+            resources.get_mut::<Vec<&'static str>>().push(name);
+        })
+    }
+
+    #[test]
+    fn keeps_the_registration_order_without_constraints() {
+        let order = run_order(vec![logger("a"), logger("b"), logger("c")]);
+        assert_eq!(order, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn runs_systems_after_the_ones_they_name() {
+        let order = run_order(vec![
+            logger("a").after("c"),
+            logger("b"),
+            logger("c").after("b"),
+            logger("d"),
+        ]);
+        assert_eq!(order, ["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn breaks_ties_by_registration_order() {
+        // "b" and "c" both wait for "d", then run in the order they were added
+        let order = run_order(vec![
+            logger("a"),
+            logger("c").after("d"),
+            logger("b").after("d"),
+            logger("d"),
+        ]);
+        assert_eq!(order, ["a", "d", "c", "b"]);
+    }
+
+    #[test]
+    fn skips_systems_whose_conditions_fail() {
+        let order = run_order(vec![
+            logger("a").run_if(resource_exists::<GameState>),
+            logger("b"),
+        ]);
+        assert_eq!(order, ["b"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "ordering cycle")]
+    fn panics_on_a_cycle() {
+        run_order(vec![
+            logger("a").after("c"),
+            logger("b").after("a"),
+            logger("c").after("b"),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no system named")]
+    fn panics_on_an_unknown_name() {
+        run_order(vec![logger("a").after("missing")]);
+    }
+}
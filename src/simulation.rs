@@ -0,0 +1,527 @@
+//! Gameplay simulation: physics, spawns, AI, damage and progression.
+//! Nothing here reads the window, the keyboard or the clock, so it can run headless.
+//! Every gameplay system runs on the fixed `GameTick`, so the frame rate never changes a run.
+use hecs::World;
+use macroquad::prelude::*;
+
+use crate::{
+    animation::Corpses,
+    boss::{boss_system, drop_treasure_system},
+    collision::{COLLISION_MATRIX_PATH, CollisionMatrix},
+    components::{
+        GameRng, GameTick, Health, Player, SpawnCounter, SpawnId, Transform, spawn_id_system,
+    },
+    damage::{contact_damage_system, damage_timers_system, enemy_death_system},
+    director::{Director, TIMELINE_PATH, Timeline, director_system},
+    enemy::{ENEMY_DEFINITIONS_DIR, EnemyRegistry, enemy_ai_system, split_system},
+    events::{
+        DamageDealt, EnemyKilled, Events, ItemPicked, PlayerDied, PlayerLeveledUp, add_event,
+        update_events,
+    },
+    flow_field::{FlowField, flow_field_system},
+    input::PlayerInput,
+    physic::{
+        PhysicsResources, RigidBodyHandleComponent, collision_register, physics_cleanup_system,
+        physics_step_system, setup_physics, sync_physics_world, sync_transforms,
+    },
+    player::{detect_player_dead, player_input_system, spawn_player},
+    progression::{
+        Upgrade, apply_upgrade, collect_pickups_system, drop_experience_system,
+        has_pending_level_up, magnet_system, roll_upgrades,
+    },
+    replay::{HASH_INTERVAL, ReplayRecorder, StateHasher},
+    schedule::{ResourceLayers, Resources, Schedule, Stage, system},
+    spatial::{SpatialIndex, spatial_index_system},
+    state::GameState,
+    weapon::{projectile_system, weapon_system},
+};
+
+/// Half the size of the camera view used for spawns when no window tells otherwise (16:9).
+pub const DEFAULT_VIEW_HALF_EXTENTS: Vec2 = vec2(400.0, 225.0);
+
+/// Game data shared by every run, loaded once.
+pub struct GameData {
+    pub enemies: EnemyRegistry,
+    pub timeline: Timeline,
+    /// Applied to new colliders, existing ones keep their interactions on reload.
+    pub collisions: CollisionMatrix,
+}
+
+impl GameData {
+    pub fn load() -> Self {
+        Self {
+            enemies: EnemyRegistry::load(ENEMY_DEFINITIONS_DIR),
+            timeline: Timeline::load(TIMELINE_PATH),
+            collisions: CollisionMatrix::load(COLLISION_MATRIX_PATH),
+        }
+    }
+
+    /// Returns `true` if the file at `path` is part of the game data.
+    pub fn is_data_file(path: &str) -> bool {
+        path == TIMELINE_PATH
+            || path == COLLISION_MATRIX_PATH
+            || (path.starts_with(ENEMY_DEFINITIONS_DIR) && path.ends_with(".ron"))
+    }
+}
+
+/// Half the size of the camera view, enemies spawn just outside of it.
+pub struct ViewHalfExtents(pub Vec2);
+
+/// Enemies killed during the run.
+pub struct Kills(pub u32);
+
+/// Upgrades offered for the pending level-up.
+#[derive(Default)]
+pub struct LevelUpChoices(pub Vec<Upgrade>);
+
+/// Everything that belongs to a single run.
+/// Restarting builds a new one, so nothing leaks from a run to the next.
+pub struct Run {
+    pub world: World,
+    /// Resources of the run only. Those of the game, such as the `GameData`, are kept apart
+    /// and given to `tick`.
+    pub resources: Resources,
+    /// The gameplay systems, in `Stage::FixedUpdate`.
+    schedule: Schedule,
+    /// Records the run when set.
+    pub recorder: Option<ReplayRecorder>,
+}
+
+impl Run {
+    /// Start a run. The same `seed` with the same inputs always plays the same run.
+    pub fn new(seed: u64) -> Self {
+        let mut world = World::new();
+        spawn_player(&mut world);
+
+        let mut resources = Resources::default();
+        resources.insert(setup_physics());
+        resources.insert(Director::default());
+        resources.insert(GameTick::default());
+        // Every random roll of the run goes through it.
+        resources.insert(GameRng::new(seed));
+        resources.insert(SpawnCounter::default());
+        resources.insert(LevelUpChoices::default());
+        resources.insert(ViewHalfExtents(DEFAULT_VIEW_HALF_EXTENTS));
+        resources.insert(Kills(0));
+        add_event::<DamageDealt>(&mut resources);
+        add_event::<EnemyKilled>(&mut resources);
+        add_event::<PlayerLeveledUp>(&mut resources);
+        add_event::<ItemPicked>(&mut resources);
+        add_event::<PlayerDied>(&mut resources);
+        resources.insert(SpatialIndex::default());
+        resources.insert(FlowField::default());
+        resources.insert(PlayerInput::default());
+
+        Self {
+            world,
+            resources,
+            schedule: gameplay_schedule(),
+            recorder: None,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.resources.get::<GameRng>().seed()
+    }
+
+    pub fn kills(&self) -> u32 {
+        self.resources.get::<Kills>().0
+    }
+
+    pub fn ticks_elapsed(&self) -> u32 {
+        self.resources.get::<GameTick>().ticks_elapsed
+    }
+
+    /// Time survived, in seconds.
+    pub fn elapsed(&self) -> f32 {
+        let game_tick = self.resources.get::<GameTick>();
+        game_tick.ticks_elapsed as f32 * game_tick.tick_rate
+    }
+
+    /// Take the time of one tick from the accumulator. Returns `false` if there isn't enough.
+    pub fn take_tick(&self) -> bool {
+        let mut game_tick = self.resources.get_mut::<GameTick>();
+        if game_tick.accumulator >= game_tick.tick_rate {
+            game_tick.accumulator -= game_tick.tick_rate;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Drop the events of the frame before the last one.
+    /// Call once at the start of every frame that runs ticks, before running them.
+    pub fn start_frame(&mut self) {
+        update_events(&self.resources);
+    }
+
+    /// Advance the simulation by `dt` seconds of real time, running as many fixed ticks as needed.
+    /// Returns the state the game should switch to.
+    pub fn update(&mut self, dt: f32, input: &PlayerInput, game: &Resources) -> GameState {
+        let mut state = GameState::Playing;
+        self.start_frame();
+
+        self.resources.get_mut::<GameTick>().accumulator += dt;
+        while self.take_tick() {
+            state = self.tick(input, game);
+            if state != GameState::Playing {
+                // The remaining time is played after the level-up, or never.
+                break;
+            }
+        }
+
+        self.interpolate();
+
+        state
+    }
+
+    /// Interpolate between the last two physics steps for smooth rendering.
+    pub fn interpolate(&mut self) {
+        sync_transforms(
+            &mut self.world,
+            &self.resources.get::<PhysicsResources>(),
+            &self.resources.get::<GameTick>(),
+        );
+    }
+
+    /// Run every gameplay system for one fixed `GameTick`, with the `GameData` of the `game`
+    /// resources. Returns the state the game should switch to.
+    pub fn tick(&mut self, input: &PlayerInput, game: &Resources) -> GameState {
+        let input = input.quantized();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_view(self.resources.get::<ViewHalfExtents>().0);
+            recorder.record_tick(&input);
+        }
+
+        self.resources.insert(input);
+        self.schedule.run(
+            Stage::FixedUpdate,
+            &mut self.world,
+            ResourceLayers::new(&self.resources, game),
+        );
+
+        let ticks_elapsed = {
+            let mut game_tick = self.resources.get_mut::<GameTick>();
+            game_tick.ticks_elapsed += 1;
+            game_tick.ticks_elapsed
+        };
+
+        if ticks_elapsed.is_multiple_of(HASH_INTERVAL) {
+            let hash = self.state_hash();
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.record_hash(hash);
+            }
+        }
+
+        if detect_player_dead(&mut self.world) {
+            let position = self
+                .world
+                .query::<&Transform>()
+                .with::<&Player>()
+                .iter()
+                .next()
+                .map(|(_id, transform)| transform.position)
+                .unwrap_or_default();
+            self.resources
+                .get_mut::<Events<PlayerDied>>()
+                .send(PlayerDied { position });
+            GameState::GameOver
+        } else if has_pending_level_up(&self.world) {
+            self.roll_level_up();
+            GameState::LevelUp
+        } else {
+            GameState::Playing
+        }
+    }
+
+    fn roll_level_up(&mut self) {
+        let choices = roll_upgrades(&self.world, &mut self.resources.get_mut::<GameRng>());
+        self.resources.get_mut::<LevelUpChoices>().0 = choices;
+    }
+
+    /// Apply the upgrade at `index` in the offered choices.
+    /// Returns the state the game should switch to.
+    pub fn choose_upgrade(&mut self, index: usize) -> GameState {
+        let Some(upgrade) = self.resources.get::<LevelUpChoices>().0.get(index).copied() else {
+            return GameState::LevelUp;
+        };
+        apply_upgrade(&mut self.world, upgrade);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_choice(index);
+        }
+
+        if has_pending_level_up(&self.world) {
+            self.roll_level_up();
+            GameState::LevelUp
+        } else {
+            GameState::Playing
+        }
+    }
+
+    /// Hash of the simulation state, to detect replay desyncs.
+    pub fn state_hash(&self) -> u64 {
+        let physics = self.resources.get::<PhysicsResources>();
+        let mut hasher = StateHasher::new();
+        hasher.write_u64(self.ticks_elapsed() as u64);
+        hasher.write_u64(self.kills() as u64);
+
+        // In spawn order, the order of the queries depends on hecs
+        let mut bodies: Vec<_> = self
+            .world
+            .query::<(&SpawnId, &RigidBodyHandleComponent)>()
+            .iter()
+            .map(|(_entity, (id, body_handle))| (*id, body_handle.0))
+            .collect();
+        bodies.sort_by_key(|(id, _body_handle)| *id);
+        for (id, body_handle) in bodies {
+            hasher.write_u64(id.0);
+            if let Some(body) = physics.rigid_body_set.get(body_handle) {
+                hasher.write_f32(body.translation().x);
+                hasher.write_f32(body.translation().y);
+                hasher.write_f32(body.linvel().x);
+                hasher.write_f32(body.linvel().y);
+            }
+        }
+
+        let mut healths: Vec<_> = self
+            .world
+            .query::<(&SpawnId, &Health)>()
+            .iter()
+            .map(|(_entity, (id, health))| (*id, health.actual, health.max))
+            .collect();
+        healths.sort_by_key(|(id, _actual, _max)| *id);
+        for (id, actual, max) in healths {
+            hasher.write_u64(id.0);
+            hasher.write_f32(actual);
+            hasher.write_f32(max);
+        }
+
+        hasher.finish()
+    }
+}
+
+/// Every gameplay system, in the order they run each tick.
+/// The order is part of the simulation: changing it changes every run and breaks old replays.
+fn gameplay_schedule() -> Schedule {
+    let mut schedule = Schedule::default();
+    schedule
+        .add_system(
+            Stage::FixedUpdate,
+            system("physics_cleanup", |world, resources| {
+                physics_cleanup_system(world, &mut resources.get_mut::<PhysicsResources>());
+            }),
+        )
+        // Update physics
+        .add_system(
+            Stage::FixedUpdate,
+            system("sync_physics_world", |world, resources| {
+                sync_physics_world(
+                    world,
+                    &mut resources.get_mut::<PhysicsResources>(),
+                    &resources.get::<GameData>().collisions,
+                );
+            })
+            .after("physics_cleanup"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("collision_register", |world, resources| {
+                collision_register(world, &resources.get::<PhysicsResources>());
+            })
+            .after("sync_physics_world"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("spatial_index", |world, resources| {
+                spatial_index_system(
+                    world,
+                    &resources.get::<PhysicsResources>(),
+                    &mut resources.get_mut::<SpatialIndex>(),
+                );
+            })
+            .after("sync_physics_world"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("flow_field", |world, resources| {
+                flow_field_system(
+                    world,
+                    &resources.get::<PhysicsResources>(),
+                    &mut resources.get_mut::<FlowField>(),
+                );
+            })
+            .after("sync_physics_world"),
+        )
+        // Do things with entities
+        .add_system(
+            Stage::FixedUpdate,
+            system("player_input", |world, resources| {
+                player_input_system(
+                    world,
+                    &mut resources.get_mut::<PhysicsResources>(),
+                    &resources.get::<PlayerInput>(),
+                );
+            })
+            .after("collision_register"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("director", |world, resources| {
+                director_system(
+                    world,
+                    &resources.get::<PhysicsResources>(),
+                    &mut resources.get_mut::<Director>(),
+                    &mut resources.get_mut::<GameRng>(),
+                    &resources.get::<GameData>(),
+                    resources.get::<ViewHalfExtents>().0,
+                    resources.get::<GameTick>().tick_rate,
+                );
+            })
+            .after("player_input"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("enemy_ai", |world, resources| {
+                enemy_ai_system(
+                    world,
+                    &mut resources.get_mut::<PhysicsResources>(),
+                    &resources.get::<SpatialIndex>(),
+                    &resources.get::<FlowField>(),
+                    resources.get::<GameTick>().tick_rate,
+                );
+            })
+            .after("director")
+            .after("flow_field"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("boss", |world, resources| {
+                boss_system(
+                    world,
+                    &mut resources.get_mut::<GameRng>(),
+                    &resources.get::<PhysicsResources>(),
+                    &resources.get::<GameData>().enemies,
+                    resources.get::<GameTick>().tick_rate,
+                );
+            })
+            .after("enemy_ai"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("weapon", |world, resources| {
+                weapon_system(
+                    world,
+                    &resources.get::<PhysicsResources>(),
+                    &resources.get::<SpatialIndex>(),
+                    resources.get::<GameTick>().tick_rate,
+                );
+            })
+            .after("boss"),
+        )
+        // Damage
+        .add_system(
+            Stage::FixedUpdate,
+            system("damage_timers", |world, resources| {
+                damage_timers_system(world, resources.get::<GameTick>().tick_rate);
+            })
+            .after("weapon"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("projectile", |world, resources| {
+                projectile_system(
+                    world,
+                    resources.get::<GameTick>().tick_rate,
+                    &mut resources.get_mut::<Events<DamageDealt>>(),
+                );
+            })
+            .after("damage_timers"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("contact_damage", |world, resources| {
+                contact_damage_system(world, &mut resources.get_mut::<Events<DamageDealt>>());
+            })
+            .after("projectile"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("enemy_death", |world, resources| {
+                let killed = enemy_death_system(
+                    world,
+                    &mut resources.get_mut::<Events<EnemyKilled>>(),
+                    resources.try_get_mut::<Corpses>().as_deref_mut(),
+                );
+                resources.get_mut::<Kills>().0 += killed;
+            })
+            .after("contact_damage"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("split", |world, resources| {
+                split_system(
+                    world,
+                    &mut resources.get_mut::<GameRng>(),
+                    &resources.get::<PhysicsResources>(),
+                    &resources.get::<GameData>().enemies,
+                );
+            })
+            .after("enemy_death"),
+        )
+        // Progression
+        .add_system(
+            Stage::FixedUpdate,
+            system("drop_experience", |world, resources| {
+                drop_experience_system(world, &resources.get::<PhysicsResources>());
+            })
+            .after("split"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("drop_treasure", |world, resources| {
+                drop_treasure_system(world, &resources.get::<PhysicsResources>());
+            })
+            .after("drop_experience"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("magnet", |world, resources| {
+                magnet_system(
+                    world,
+                    &mut resources.get_mut::<PhysicsResources>(),
+                    &resources.get::<SpatialIndex>(),
+                );
+            })
+            .after("drop_treasure"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("collect_pickups", |world, resources| {
+                collect_pickups_system(
+                    world,
+                    &mut resources.get_mut::<Events<ItemPicked>>(),
+                    &mut resources.get_mut::<Events<PlayerLeveledUp>>(),
+                );
+            })
+            .after("magnet"),
+        )
+        .add_system(
+            Stage::FixedUpdate,
+            system("physics_step", |_world, resources| {
+                physics_step_system(
+                    &mut resources.get_mut::<PhysicsResources>(),
+                    &resources.get::<GameTick>(),
+                );
+            })
+            .after("collect_pickups"),
+        )
+        // Last, so every entity of the tick is numbered before the state hash
+        .add_system(
+            Stage::FixedUpdate,
+            system("spawn_ids", |world, resources| {
+                spawn_id_system(world, &mut resources.get_mut::<SpawnCounter>());
+            })
+            .after("physics_step"),
+        );
+    schedule
+}
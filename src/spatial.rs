@@ -0,0 +1,330 @@
+//! Uniform grid of entity positions, for neighbour and target queries.
+//!
+//! The index is rebuilt at the start of every tick, after the new bodies entered the physics
+//! world. Positions are the collider centers of the tick: `Transform` is interpolated for
+//! rendering and lags behind. Results never depend on `HashMap` order, so queries are
+//! deterministic.
+use std::collections::HashMap;
+
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+
+use crate::{
+    components::{Despawn, Enemy},
+    physic::{ColliderHandleComponent, PhysicsResources, collider_center},
+    progression::XpGem,
+};
+
+/// Side of a cell, in pixels. About twice the size of an enemy.
+pub const CELL_SIZE: f32 = 64.0;
+
+type Cell = (i32, i32);
+
+pub struct SpatialHash {
+    cell_size: f32,
+    /// Entities of each cell, in insertion order.
+    cells: HashMap<Cell, Vec<(Entity, Vec2)>>,
+    /// Lowest and highest occupied cells, so searches know when to stop.
+    bounds: Option<(Cell, Cell)>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            bounds: None,
+        }
+    }
+
+    /// Remove every entity. Cells used since the last clear keep their allocation.
+    pub fn clear(&mut self) {
+        self.cells.retain(|_cell, entities| {
+            let used = !entities.is_empty();
+            entities.clear();
+            used
+        });
+        self.bounds = None;
+    }
+
+    pub fn insert(&mut self, entity: Entity, position: Vec2) {
+        let cell = self.cell(position);
+        self.cells.entry(cell).or_default().push((entity, position));
+        self.bounds = Some(match self.bounds {
+            Some((min, max)) => (
+                (min.0.min(cell.0), min.1.min(cell.1)),
+                (max.0.max(cell.0), max.1.max(cell.1)),
+            ),
+            None => (cell, cell),
+        });
+    }
+
+    fn cell(&self, position: Vec2) -> Cell {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn entities_in(&self, cell: Cell) -> &[(Entity, Vec2)] {
+        self.cells.get(&cell).map_or(&[], Vec::as_slice)
+    }
+
+    /// Entities inside `rect`, edges included.
+    pub fn query_aabb(&self, rect: Rect) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        let (low, high) = (rect.point(), rect.point() + rect.size());
+        let min = self.cell(low);
+        let max = self.cell(high);
+        (min.0..=max.0)
+            .flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+            .flat_map(|cell| self.entities_in(cell).iter().copied())
+            .filter(move |(_entity, position)| {
+                position.cmpge(low).all() && position.cmple(high).all()
+            })
+    }
+
+    /// Entities within `radius` of `center`, the circle included.
+    pub fn query_radius(
+        &self,
+        center: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        let rect = Rect::new(
+            center.x - radius,
+            center.y - radius,
+            radius * 2.0,
+            radius * 2.0,
+        );
+        self.query_aabb(rect)
+            .filter(move |(_entity, position)| position.distance_squared(center) <= radius * radius)
+    }
+
+    /// The `k` entities closest to `center`, closest first.
+    /// Searches rings of cells around `center`, so a far target costs more than a close one.
+    pub fn k_nearest(&self, center: Vec2, k: usize) -> Vec<(Entity, Vec2)> {
+        let Some((min, max)) = self.bounds else {
+            return Vec::new();
+        };
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let origin = self.cell(center);
+        // Rings past this one only hold empty cells
+        let last_ring = [
+            origin.0 - min.0,
+            max.0 - origin.0,
+            origin.1 - min.1,
+            max.1 - origin.1,
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+        .max(0);
+
+        let mut found: Vec<(f32, Entity, Vec2)> = Vec::new();
+        for ring in 0..=last_ring {
+            for cell in ring_cells(origin, ring) {
+                for (entity, position) in self.entities_in(cell) {
+                    found.push((position.distance_squared(center), *entity, *position));
+                }
+            }
+
+            // Entities of the next rings are at least `ring` cells away
+            if found.len() >= k {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+                found.truncate(k);
+                let reach = ring as f32 * self.cell_size;
+                if found[k - 1].0 <= reach * reach {
+                    break;
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        found
+            .into_iter()
+            .take(k)
+            .map(|(_distance, entity, position)| (entity, position))
+            .collect()
+    }
+
+    /// The entity closest to `center`.
+    pub fn nearest(&self, center: Vec2) -> Option<(Entity, Vec2)> {
+        self.k_nearest(center, 1).into_iter().next()
+    }
+}
+
+/// Cells at exactly `ring` cells from `origin`, the square outline around it.
+fn ring_cells(origin: Cell, ring: i32) -> impl Iterator<Item = Cell> {
+    (-ring..=ring).flat_map(move |dx| {
+        (-ring..=ring)
+            .filter(move |dy| dx.abs() == ring || dy.abs() == ring)
+            .map(move |dy| (origin.0 + dx, origin.1 + dy))
+    })
+}
+
+/// Positions of the entities gameplay searches through.
+pub struct SpatialIndex {
+    pub enemies: SpatialHash,
+    pub pickups: SpatialHash,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self {
+            enemies: SpatialHash::new(CELL_SIZE),
+            pickups: SpatialHash::new(CELL_SIZE),
+        }
+    }
+}
+
+/// Rebuild the index from the colliders of the tick.
+pub fn spatial_index_system(world: &World, physics: &PhysicsResources, index: &mut SpatialIndex) {
+    index.enemies.clear();
+    for (entity, handle) in world
+        .query::<&ColliderHandleComponent>()
+        .with::<&Enemy>()
+        .without::<&Despawn>()
+        .iter()
+    {
+        if let Some(center) = collider_center(physics, handle) {
+            index.enemies.insert(entity, center);
+        }
+    }
+
+    index.pickups.clear();
+    for (entity, handle) in world
+        .query::<&ColliderHandleComponent>()
+        .with::<&XpGem>()
+        .without::<&Despawn>()
+        .iter()
+    {
+        if let Some(center) = collider_center(physics, handle) {
+            index.pickups.insert(entity, center);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::GameRng;
+
+    /// Random points, plus points on the corners and edges of cells, and a duplicate.
+    fn points(world: &mut World) -> Vec<(Entity, Vec2)> {
+        let mut rng = GameRng::new(7);
+        let mut positions: Vec<Vec2> = (0..200)
+            .map(|_| vec2(rng.gen_range(-300.0, 300.0), rng.gen_range(-300.0, 300.0)))
+            .collect();
+        for i in -3..=3 {
+            positions.push(vec2(i as f32 * CELL_SIZE, 0.0));
+            positions.push(vec2(i as f32 * CELL_SIZE, i as f32 * CELL_SIZE));
+            positions.push(vec2(CELL_SIZE / 2.0, i as f32 * CELL_SIZE));
+        }
+        positions.push(positions[0]);
+        positions
+            .into_iter()
+            .map(|position| (world.spawn(()), position))
+            .collect()
+    }
+
+    fn hash_of(points: &[(Entity, Vec2)]) -> SpatialHash {
+        let mut hash = SpatialHash::new(CELL_SIZE);
+        for (entity, position) in points.iter() {
+            hash.insert(*entity, *position);
+        }
+        hash
+    }
+
+    fn sorted(mut found: Vec<(Entity, Vec2)>) -> Vec<(Entity, Vec2)> {
+        found.sort_by_key(|(entity, _position)| *entity);
+        found
+    }
+
+    /// Centers of the queries: random ones, and some on cell edges.
+    fn centers() -> Vec<Vec2> {
+        let mut rng = GameRng::new(11);
+        let mut centers: Vec<Vec2> = (0..30)
+            .map(|_| vec2(rng.gen_range(-400.0, 400.0), rng.gen_range(-400.0, 400.0)))
+            .collect();
+        centers.extend([
+            Vec2::ZERO,
+            vec2(CELL_SIZE, -CELL_SIZE),
+            vec2(CELL_SIZE / 2.0, 2.0 * CELL_SIZE),
+            vec2(1000.0, 1000.0),
+        ]);
+        centers
+    }
+
+    #[test]
+    fn query_aabb_matches_brute_force() {
+        let mut world = World::new();
+        let points = points(&mut world);
+        let hash = hash_of(&points);
+        for center in centers() {
+            for size in [0.0, CELL_SIZE, 100.0, 2.0 * CELL_SIZE] {
+                let rect = Rect::new(center.x, center.y, size, size * 0.5);
+                let expected: Vec<_> = points
+                    .iter()
+                    .copied()
+                    .filter(|(_entity, position)| {
+                        position.x >= rect.left()
+                            && position.x <= rect.right()
+                            && position.y >= rect.top()
+                            && position.y <= rect.bottom()
+                    })
+                    .collect();
+                assert_eq!(sorted(hash.query_aabb(rect).collect()), sorted(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn query_radius_matches_brute_force() {
+        let mut world = World::new();
+        let points = points(&mut world);
+        let hash = hash_of(&points);
+        for center in centers() {
+            for radius in [0.0, 10.0, CELL_SIZE, 150.0] {
+                let expected: Vec<_> = points
+                    .iter()
+                    .copied()
+                    .filter(|(_entity, position)| position.distance(center) <= radius)
+                    .collect();
+                assert_eq!(
+                    sorted(hash.query_radius(center, radius).collect()),
+                    sorted(expected)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn k_nearest_matches_brute_force() {
+        let mut world = World::new();
+        let points = points(&mut world);
+        let hash = hash_of(&points);
+        for center in centers() {
+            let mut expected = points.clone();
+            expected.sort_by(|a, b| {
+                a.1.distance_squared(center)
+                    .total_cmp(&b.1.distance_squared(center))
+                    .then(a.0.cmp(&b.0))
+            });
+            // Past the number of entities, every entity is returned
+            for k in [0, 1, 5, 40, points.len(), points.len() + 10] {
+                let expected: Vec<_> = expected.iter().copied().take(k).collect();
+                assert_eq!(hash.k_nearest(center, k), expected);
+            }
+            assert_eq!(hash.nearest(center), expected.first().copied());
+        }
+    }
+
+    #[test]
+    fn empty_hash_finds_nothing() {
+        let hash = SpatialHash::new(CELL_SIZE);
+        assert!(hash.k_nearest(Vec2::ZERO, 3).is_empty());
+        assert_eq!(hash.query_radius(Vec2::ZERO, 100.0).count(), 0);
+    }
+}
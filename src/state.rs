@@ -0,0 +1,14 @@
+/// Drive which systems run, and which screen is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
+    /// Loading the assets needed to start.
+    Loading,
+    Title,
+    Playing,
+    Paused,
+    /// Rebinding controls, from the pause menu.
+    Controls,
+    /// Waiting for the player to pick an upgrade.
+    LevelUp,
+    GameOver,
+}
@@ -0,0 +1,139 @@
+//! Steering forces that move enemies as a crowd rather than a single stack.
+//!
+//! Each force is a direction of length at most 1. They are weighted per enemy archetype and
+//! summed into the direction the enemy walks in, its speed is applied after.
+use hecs::Entity;
+use macroquad::prelude::*;
+use rapier2d::prelude::*;
+use serde::Deserialize;
+
+use crate::{collision::PhysicsLayer, spatial::SpatialHash};
+
+/// Weights and ranges of the steering forces, from the enemy definition.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Steering {
+    /// Walk to the target, along the flow field around walls.
+    pub seek: f32,
+    /// Move away from neighbours closer than `separation_radius`.
+    pub separation: f32,
+    /// Move to the center of the neighbours closer than `cohesion_radius`.
+    pub cohesion: f32,
+    /// Turn away from walls up to `look_ahead` pixels ahead.
+    pub avoidance: f32,
+    pub separation_radius: f32,
+    pub cohesion_radius: f32,
+    pub look_ahead: f32,
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Self {
+            seek: 1.0,
+            separation: 1.2,
+            cohesion: 0.2,
+            avoidance: 2.0,
+            separation_radius: 40.0,
+            cohesion_radius: 96.0,
+            look_ahead: 48.0,
+        }
+    }
+}
+
+/// Filter of the obstacle queries: walls only, as seen by enemies.
+pub fn obstacle_filter() -> QueryFilter<'static> {
+    QueryFilter::default()
+        .exclude_sensors()
+        .groups(InteractionGroups::new(
+            PhysicsLayer::Enemy.group(),
+            PhysicsLayer::Wall.group(),
+            InteractionTestMode::And,
+        ))
+}
+
+impl Steering {
+    /// Direction `entity`, at `position`, should walk in to follow the unit `seek` heading.
+    /// `neighbours` holds the other members of the crowd, `obstacles` is filtered with
+    /// `obstacle_filter`. `phase` is the angle `entity` leaves along when stacked on another.
+    pub fn direction(
+        &self,
+        entity: Entity,
+        phase: f32,
+        position: Vec2,
+        seek: Vec2,
+        neighbours: &SpatialHash,
+        obstacles: &QueryPipeline,
+    ) -> Vec2 {
+        let mut direction = seek * self.seek;
+
+        if self.separation > 0.0 || self.cohesion > 0.0 {
+            let (separation, cohesion) = self.crowd(entity, phase, position, neighbours);
+            direction += separation * self.separation + cohesion * self.cohesion;
+        }
+        if self.avoidance > 0.0 {
+            direction += self.avoid(position, seek, obstacles) * self.avoidance;
+        }
+
+        direction.clamp_length_max(1.0)
+    }
+
+    /// Separation and cohesion from the neighbours within range.
+    fn crowd(
+        &self,
+        entity: Entity,
+        phase: f32,
+        position: Vec2,
+        neighbours: &SpatialHash,
+    ) -> (Vec2, Vec2) {
+        let mut separation = Vec2::ZERO;
+        let mut center = Vec2::ZERO;
+        let mut count = 0;
+        let range = self.separation_radius.max(self.cohesion_radius);
+
+        for (other, other_position) in neighbours.query_radius(position, range) {
+            if other == entity {
+                continue;
+            }
+            let offset = position - other_position;
+            let distance = offset.length();
+
+            if distance < self.separation_radius {
+                // Stacked enemies split in a direction of their own
+                let away = if distance > 0.0 {
+                    offset / distance
+                } else {
+                    Vec2::from_angle(phase)
+                };
+                separation += away * (1.0 - distance / self.separation_radius);
+            }
+            if distance < self.cohesion_radius {
+                center += other_position;
+                count += 1;
+            }
+        }
+
+        let cohesion = if count > 0 {
+            (center / count as f32 - position).normalize_or_zero()
+        } else {
+            Vec2::ZERO
+        };
+        (separation.clamp_length_max(1.0), cohesion)
+    }
+
+    /// Push along the normal of the wall ahead, harder as it gets closer.
+    fn avoid(&self, position: Vec2, heading: Vec2, obstacles: &QueryPipeline) -> Vec2 {
+        if heading == Vec2::ZERO {
+            return Vec2::ZERO;
+        }
+        let ray = Ray::new(
+            point![position.x, position.y],
+            vector![heading.x, heading.y],
+        );
+        match obstacles.cast_ray_and_get_normal(&ray, self.look_ahead, true) {
+            Some((_collider, hit)) => {
+                vec2(hit.normal.x, hit.normal.y) * (1.0 - hit.time_of_impact / self.look_ahead)
+            }
+            None => Vec2::ZERO,
+        }
+    }
+}
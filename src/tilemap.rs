@@ -0,0 +1,268 @@
+//! Infinite floor, drawn from a tileset.
+//!
+//! The floor is cut in square chunks, generated the first time they are seen.
+//! Each chunk has its own generator seeded from the run seed and its coordinates,
+//! so a run always gets the same floor, whatever the order chunks are visited in.
+use std::collections::HashMap;
+
+use log::{error, info};
+use macroquad::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    asset_server::{AssetId, AssetServer},
+    components::GameRng,
+};
+
+pub const FLOOR_PATH: &str = "assets/floor.ron";
+
+/// Chunks this far outside the view are kept, so walking back and forth doesn't regenerate them.
+const CHUNK_KEEP_MARGIN: i32 = 2;
+
+/// A tile of the tileset, and how often it is picked.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct TileVariant {
+    /// Index in the tileset, left to right then top to bottom.
+    pub tile: u32,
+    pub weight: f32,
+}
+
+/// Tiles scattered over the ground.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecorationLayer {
+    pub tiles: Vec<TileVariant>,
+    /// Chance for each ground tile to get a decoration.
+    pub density: f32,
+    /// Size of a decoration relative to a tile.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// The floor of a run, loaded from `floor.ron`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FloorDefinition {
+    pub tileset: String,
+    /// Size of a tile, in tileset pixels and world units.
+    pub tile_size: f32,
+    /// Tiles per side of a chunk.
+    pub chunk_size: u32,
+    pub ground: Vec<TileVariant>,
+    #[serde(default)]
+    pub decorations: Vec<DecorationLayer>,
+}
+
+impl FloorDefinition {
+    /// Load the floor. An invalid file is logged and gives no floor.
+    pub fn load(path: &str) -> Option<Self> {
+        let floor = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                ron::from_str::<FloorDefinition>(&content).map_err(|e| e.to_string())
+            });
+
+        match floor {
+            Ok(floor) if floor.chunk_size > 0 && floor.tile_size > 0.0 => {
+                info!("Floor loaded: {} ({})", floor.tileset, path);
+                Some(floor)
+            }
+            Ok(_) => {
+                error!("Loading floor {}: empty chunks or tiles", path);
+                None
+            }
+            Err(e) => {
+                error!("Loading floor {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn chunk_world_size(&self) -> f32 {
+        self.tile_size * self.chunk_size as f32
+    }
+}
+
+/// Pick a tile, `None` if there is nothing to pick from.
+fn pick_tile(rng: &mut GameRng, variants: &[TileVariant]) -> Option<u32> {
+    let total: f32 = variants.iter().map(|variant| variant.weight).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut roll = rng.gen_range(0.0, total);
+    for variant in variants {
+        if roll < variant.weight {
+            return Some(variant.tile);
+        }
+        roll -= variant.weight;
+    }
+    variants.last().map(|variant| variant.tile)
+}
+
+struct Decoration {
+    tile: u32,
+    /// Top-left corner, in world units.
+    position: Vec2,
+    size: f32,
+}
+
+struct Chunk {
+    /// One tile per cell, row by row.
+    ground: Vec<Option<u32>>,
+    decorations: Vec<Decoration>,
+}
+
+/// The generated part of the floor.
+pub struct Tilemap {
+    definition: FloorDefinition,
+    tileset: AssetId,
+    seed: u64,
+    chunks: HashMap<IVec2, Chunk>,
+}
+
+impl Tilemap {
+    pub fn new(definition: FloorDefinition, seed: u64) -> Self {
+        Self {
+            tileset: AssetServer::compute_id(&definition.tileset),
+            definition,
+            seed,
+            chunks: HashMap::new(),
+        }
+    }
+
+    /// Seed of the chunk at `coord`, mixed so neighbour chunks don't look alike.
+    fn chunk_seed(&self, coord: IVec2) -> u64 {
+        // SplitMix64 finalizer
+        let mut z = self.seed
+            ^ (coord.x as u32 as u64).wrapping_mul(0x9E3779B97F4A7C15)
+            ^ (coord.y as u32 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    fn generate_chunk(&self, coord: IVec2) -> Chunk {
+        let definition = &self.definition;
+        let mut rng = GameRng::new(self.chunk_seed(coord));
+        let origin = coord.as_vec2() * definition.chunk_world_size();
+        let cells = definition.chunk_size * definition.chunk_size;
+
+        let ground = (0..cells)
+            .map(|_| pick_tile(&mut rng, &definition.ground))
+            .collect();
+
+        let mut decorations = Vec::new();
+        for layer in definition.decorations.iter() {
+            let size = definition.tile_size * layer.scale;
+            for cell in 0..cells {
+                if rng.gen_range(0.0, 1.0) >= layer.density {
+                    continue;
+                }
+                let Some(tile) = pick_tile(&mut rng, &layer.tiles) else {
+                    continue;
+                };
+                // Anywhere in the cell, without overflowing it
+                let free = (definition.tile_size - size).max(0.0);
+                let offset = vec2(rng.gen_range(0.0, 1.0), rng.gen_range(0.0, 1.0)) * free;
+                let cell_position = vec2(
+                    (cell % definition.chunk_size) as f32,
+                    (cell / definition.chunk_size) as f32,
+                ) * definition.tile_size;
+                decorations.push(Decoration {
+                    tile,
+                    position: origin + cell_position + offset,
+                    size,
+                });
+            }
+        }
+
+        Chunk {
+            ground,
+            decorations,
+        }
+    }
+
+    /// Area of `tile` in the tileset texture.
+    fn tile_source(&self, texture: &Texture2D, tile: u32) -> Rect {
+        let tile_size = self.definition.tile_size;
+        let columns = ((texture.width() / tile_size) as u32).max(1);
+        Rect::new(
+            (tile % columns) as f32 * tile_size,
+            (tile / columns) as f32 * tile_size,
+            tile_size,
+            tile_size,
+        )
+    }
+
+    /// Draw the chunks overlapping `view_rect`, generating the new ones.
+    pub fn draw(&mut self, asset_server: &AssetServer, view_rect: Rect) {
+        let chunk_world_size = self.definition.chunk_world_size();
+        let min = (view_rect.point() / chunk_world_size).floor().as_ivec2();
+        let max = ((view_rect.point() + view_rect.size()) / chunk_world_size)
+            .floor()
+            .as_ivec2();
+
+        self.chunks.retain(|coord, _chunk| {
+            coord.cmpge(min - CHUNK_KEEP_MARGIN).all() && coord.cmple(max + CHUNK_KEEP_MARGIN).all()
+        });
+
+        // The tileset is optional, nothing is drawn until it loaded
+        let Some(texture) = asset_server.find_texture(self.tileset) else {
+            return;
+        };
+        let tile_size = self.definition.tile_size;
+        let chunk_size = self.definition.chunk_size;
+
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let coord = ivec2(x, y);
+                if !self.chunks.contains_key(&coord) {
+                    let chunk = self.generate_chunk(coord);
+                    self.chunks.insert(coord, chunk);
+                }
+                let chunk = &self.chunks[&coord];
+                let origin = coord.as_vec2() * chunk_world_size;
+
+                for (cell, tile) in chunk.ground.iter().enumerate() {
+                    let Some(tile) = tile else {
+                        continue;
+                    };
+                    let cell = cell as u32;
+                    let position = origin
+                        + vec2((cell % chunk_size) as f32, (cell / chunk_size) as f32) * tile_size;
+                    if !view_rect.overlaps(&Rect::new(position.x, position.y, tile_size, tile_size))
+                    {
+                        continue;
+                    }
+                    draw_texture_ex(
+                        texture,
+                        position.x,
+                        position.y,
+                        WHITE,
+                        DrawTextureParams {
+                            dest_size: Some(vec2(tile_size, tile_size)),
+                            source: Some(self.tile_source(texture, *tile)),
+                            ..Default::default()
+                        },
+                    );
+                }
+
+                for decoration in chunk.decorations.iter() {
+                    draw_texture_ex(
+                        texture,
+                        decoration.position.x,
+                        decoration.position.y,
+                        WHITE,
+                        DrawTextureParams {
+                            dest_size: Some(vec2(decoration.size, decoration.size)),
+                            source: Some(self.tile_source(texture, decoration.tile)),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Screen-space overlays, drawn on top of the world.
+use hecs::World;
+use macroquad::prelude::*;
+
+use crate::{
+    asset_server::LoadProgress,
+    audio::{AudioSettings, Volume},
+    boss::Boss,
+    camera::{CameraController, interpolated_center},
+    components::{Despawn, Health, Player, Transform},
+    input::{Action, BINDINGS_FILE, InputMap},
+    physic::{ColliderHandleComponent, PhysicsResources},
+    progression::{Experience, Level, Upgrade},
+};
+
+/// Distance between the edge of the screen and the off-screen boss markers, in pixels.
+const BOSS_MARKER_MARGIN: f32 = 24.0;
+
+/// Darken the whole screen, so the overlay text is readable over the world.
+fn draw_backdrop() {
+    draw_rectangle(
+        0.0,
+        0.0,
+        screen_width(),
+        screen_height(),
+        Color::new(0.0, 0.0, 0.0, 0.6),
+    );
+}
+
+/// Draw a line of text horizontally centered on the screen.
+fn draw_centered_text(text: &str, y: f32, font_size: f32, color: Color) {
+    let dimensions = measure_text(text, None, font_size as u16, 1.0);
+    draw_text(
+        text,
+        (screen_width() - dimensions.width) / 2.0,
+        y,
+        font_size,
+        color,
+    );
+}
+
+/// Progress bar of the assets needed to start.
+pub fn draw_loading_screen(progress: LoadProgress) {
+    set_default_camera();
+    let center = screen_height() / 2.0;
+    let width = screen_width() / 2.0;
+    let x = (screen_width() - width) / 2.0;
+    draw_centered_text("LOADING", center - 40.0, 64.0, WHITE);
+    draw_rectangle(x, center, width, 16.0, DARKGRAY);
+    draw_rectangle(x, center, width * progress.ratio(), 16.0, SKYBLUE);
+    draw_centered_text(
+        &format!("{}/{}", progress.done(), progress.total),
+        center + 50.0,
+        32.0,
+        LIGHTGRAY,
+    );
+    if progress.failed > 0 {
+        draw_centered_text(
+            &format!("{} failed, see the log", progress.failed),
+            center + 90.0,
+            32.0,
+            RED,
+        );
+    }
+}
+
+pub fn draw_title_screen(input: &InputMap) {
+    set_default_camera();
+    let center = screen_height() / 2.0;
+    draw_centered_text("VAMP SURVIVOR", center - 40.0, 64.0, WHITE);
+    draw_centered_text(
+        &format!("Press {} to start", input.label(Action::Confirm)),
+        center + 20.0,
+        32.0,
+        LIGHTGRAY,
+    );
+}
+
+/// Pause screen, with the volumes, the selected one highlighted.
+pub fn draw_pause_overlay(input: &InputMap, settings: &AudioSettings, selected: usize) {
+    set_default_camera();
+    draw_backdrop();
+    let center = screen_height() / 2.0;
+    draw_centered_text("PAUSED", center - 120.0, 64.0, WHITE);
+    draw_centered_text(
+        &format!("Press {} to resume", input.label(Action::Pause)),
+        center - 70.0,
+        32.0,
+        LIGHTGRAY,
+    );
+    draw_centered_text(
+        &format!("Press {} for controls", input.label(Action::Controls)),
+        center - 30.0,
+        32.0,
+        LIGHTGRAY,
+    );
+
+    let middle = screen_width() / 2.0;
+    for (i, volume) in Volume::ALL.iter().enumerate() {
+        let value = settings.volume(*volume);
+        let color = if i == selected { GOLD } else { WHITE };
+        let y = center + 40.0 + i as f32 * 36.0;
+        draw_text(volume.name(), middle - 220.0, y, 28.0, color);
+        draw_rectangle(middle + 20.0, y - 14.0, 160.0, 14.0, DARKGRAY);
+        draw_rectangle(middle + 20.0, y - 14.0, 160.0 * value, 14.0, color);
+        draw_text(
+            &format!("{:.0}%", value * 100.0),
+            middle + 190.0,
+            y,
+            28.0,
+            color,
+        );
+    }
+    draw_centered_text(
+        &format!(
+            "{}/{}: change volume",
+            input.label(Action::MoveLeft),
+            input.label(Action::MoveRight)
+        ),
+        screen_height() - 30.0,
+        28.0,
+        LIGHTGRAY,
+    );
+}
+
+/// Every action with its bindings, the selected one highlighted.
+pub fn draw_controls_screen(input: &InputMap, selected: usize, listening: bool) {
+    set_default_camera();
+    draw_backdrop();
+    let top = 80.0;
+    draw_centered_text("CONTROLS", top, 64.0, WHITE);
+    for (i, action) in Action::ALL.iter().enumerate() {
+        let bindings = if listening && i == selected {
+            "press a key or button...".to_owned()
+        } else {
+            input
+                .bindings(*action)
+                .iter()
+                .map(|binding| binding.name())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let color = if i == selected { GOLD } else { WHITE };
+        let y = top + 50.0 + i as f32 * 28.0;
+        draw_text(action.name(), 80.0, y, 28.0, color);
+        draw_text(&bindings, 320.0, y, 28.0, color);
+    }
+    draw_centered_text(
+        &format!(
+            "{}: rebind    {}: back    sticks: edit {}",
+            input.label(Action::Confirm),
+            input.label(Action::Pause),
+            BINDINGS_FILE
+        ),
+        screen_height() - 30.0,
+        28.0,
+        LIGHTGRAY,
+    );
+}
+
+pub fn draw_level_up_overlay(input: &InputMap, choices: &[Upgrade]) {
+    set_default_camera();
+    draw_backdrop();
+    let top = screen_height() / 2.0 - 80.0;
+    draw_centered_text("LEVEL UP!", top, 64.0, GOLD);
+    let actions = [Action::Choice1, Action::Choice2, Action::Choice3];
+    for (i, (upgrade, action)) in choices.iter().zip(actions).enumerate() {
+        draw_centered_text(
+            &format!("[{}] {}", input.label(action), upgrade.description()),
+            top + 60.0 + i as f32 * 40.0,
+            32.0,
+            WHITE,
+        );
+    }
+}
+
+/// Experience bar and level, at the top of the screen.
+/// Bosses out of the view are pointed at from the edge of the screen.
+pub fn draw_hud(world: &World, camera: &CameraController, physics: &PhysicsResources) {
+    set_default_camera();
+    for (_id, (experience, level)) in world
+        .query::<(&Experience, &Level)>()
+        .with::<&Player>()
+        .iter()
+    {
+        let ratio = experience.current as f32 / experience.to_next as f32;
+        draw_rectangle(0.0, 0.0, screen_width(), 12.0, DARKGRAY);
+        draw_rectangle(0.0, 0.0, screen_width() * ratio, 12.0, SKYBLUE);
+        draw_text(&format!("LV {}", level.0), 8.0, 32.0, 24.0, WHITE);
+    }
+
+    // Boss health bars, under the experience bar
+    for (index, (_id, (boss, health))) in world
+        .query::<(&Boss, &Health)>()
+        .without::<&Despawn>()
+        .iter()
+        .enumerate()
+    {
+        let ratio = (health.actual / health.max).clamp(0.0, 1.0);
+        let width = screen_width() * 0.6;
+        let x = (screen_width() - width) / 2.0;
+        let y = 40.0 + index as f32 * 40.0;
+        draw_centered_text(&boss.name.to_uppercase(), y, 24.0, WHITE);
+        draw_rectangle(x, y + 8.0, width, 14.0, DARKGRAY);
+        draw_rectangle(x, y + 8.0, width * ratio, 14.0, RED);
+        draw_rectangle_lines(x, y + 8.0, width, 14.0, 2.0, WHITE);
+    }
+
+    let screen_center = vec2(screen_width(), screen_height()) / 2.0;
+    let marker_extents = screen_center - BOSS_MARKER_MARGIN;
+    for (_id, (transform, handle)) in world
+        .query::<(&Transform, Option<&ColliderHandleComponent>)>()
+        .with::<&Boss>()
+        .without::<&Despawn>()
+        .iter()
+    {
+        let boss = camera.world_to_screen(interpolated_center(transform, handle, physics));
+        let offset = boss - screen_center;
+        if offset.x.abs() <= screen_center.x && offset.y.abs() <= screen_center.y {
+            continue;
+        }
+        // Where the line to the boss leaves the marker area
+        let scale = (marker_extents / offset.abs()).min_element();
+        let marker = screen_center + offset * scale;
+        let direction = offset.normalize();
+        let side = direction.perp() * 10.0;
+        draw_triangle(marker + direction * 14.0, marker + side, marker - side, RED);
+    }
+}
+
+pub fn draw_game_over_screen(input: &InputMap, survived: f32) {
+    set_default_camera();
+    draw_backdrop();
+    let center = screen_height() / 2.0;
+    let seconds = survived as u32;
+    draw_centered_text("GAME OVER", center - 40.0, 64.0, RED);
+    draw_centered_text(
+        &format!("You survived {:02}:{:02}", seconds / 60, seconds % 60),
+        center + 10.0,
+        32.0,
+        WHITE,
+    );
+    draw_centered_text(
+        &format!("Press {} to restart", input.label(Action::Confirm)),
+        center + 50.0,
+        32.0,
+        LIGHTGRAY,
+    );
+}
+
+/// Replay speed and controls, at the bottom of the screen.
+pub fn draw_replay_hud(input: &InputMap, speed: f32, paused: bool, finished: bool) {
+    set_default_camera();
+    let status = if finished {
+        "REPLAY FINISHED".to_owned()
+    } else if paused {
+        format!("REPLAY PAUSED - {}: step", input.label(Action::ReplayStep))
+    } else {
+        format!("REPLAY x{}", speed)
+    };
+    draw_text(
+        &format!(
+            "{}  |  {}: pause  {}: speed",
+            status,
+            input.label(Action::ReplayPause),
+            input.label(Action::ReplaySpeed)
+        ),
+        8.0,
+        screen_height() - 12.0,
+        24.0,
+        WHITE,
+    );
+}
@@ -0,0 +1,271 @@
+use hecs::{Entity, World};
+use macroquad::prelude::*;
+use rapier2d::prelude::*;
+
+use crate::{
+    animation::Animator,
+    asset_server::{self},
+    collision::{PhysicsLayer, sensor_collider},
+    components::{Despawn, Enemy, Player, Sprite, Transform},
+    damage::apply_damage,
+    events::{DamageDealt, Events},
+    physic::{CollideWith, ColliderHandleComponent, PhysicsResources, collider_center},
+    progression::Stats,
+    spatial::SpatialIndex,
+};
+
+/// Size in pixels of one fireball frame in `fireball.aseprite`.
+const FIREBALL_SIZE: f32 = 16.0;
+
+/// Highest level a weapon can reach through upgrades.
+pub const MAX_WEAPON_LEVEL: u32 = 8;
+
+/// Every weapon the player can get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeaponKind {
+    /// A single fireball at the nearest enemy.
+    Fireball,
+    /// A fan of piercing fireballs, slower to recharge.
+    Volley,
+}
+
+impl WeaponKind {
+    pub const ALL: [WeaponKind; 2] = [WeaponKind::Fireball, WeaponKind::Volley];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WeaponKind::Fireball => "Fireball",
+            WeaponKind::Volley => "Volley",
+        }
+    }
+}
+
+/// An auto-firing weapon.
+pub struct Weapon {
+    pub kind: WeaponKind,
+    pub level: u32,
+    /// Time in seconds between two shots.
+    pub cooldown: f32,
+    pub timer: f32,
+    pub projectile_speed: f32,
+    /// Number of projectiles per shot, spread around the aim direction.
+    pub projectile_count: u32,
+    /// Angle in radians between two projectiles of the same shot.
+    pub spread: f32,
+    /// Number of enemies a projectile can pass through before being destroyed.
+    pub pierce: u32,
+    pub damage: f32,
+    /// Time in seconds before a projectile disappears.
+    pub projectile_lifetime: f32,
+}
+
+impl Weapon {
+    pub fn new(kind: WeaponKind) -> Self {
+        match kind {
+            WeaponKind::Fireball => Self {
+                kind,
+                level: 1,
+                cooldown: 1.0,
+                timer: 0.0,
+                projectile_speed: 300.0,
+                projectile_count: 1,
+                spread: 0.0,
+                pierce: 0,
+                damage: 10.0,
+                projectile_lifetime: 3.0,
+            },
+            WeaponKind::Volley => Self {
+                kind,
+                level: 1,
+                cooldown: 2.0,
+                timer: 0.0,
+                projectile_speed: 250.0,
+                projectile_count: 3,
+                spread: 0.25,
+                pierce: 1,
+                damage: 6.0,
+                projectile_lifetime: 2.0,
+            },
+        }
+    }
+
+    /// Improve the weapon by one level.
+    pub fn level_up(&mut self) {
+        self.level += 1;
+        match self.kind {
+            WeaponKind::Fireball => {
+                self.damage += 5.0;
+                self.cooldown *= 0.9;
+                if self.level.is_multiple_of(3) {
+                    self.pierce += 1;
+                }
+            }
+            WeaponKind::Volley => {
+                self.damage += 3.0;
+                if self.level.is_multiple_of(2) {
+                    self.projectile_count += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Every weapon held by an entity.
+pub struct Weapons(pub Vec<Weapon>);
+
+/// A projectile fired by a `Weapon`.
+pub struct Projectile {
+    pub damage: f32,
+    pub pierce: u32,
+    pub lifetime: f32,
+    /// Enemies already hit, so a piercing projectile damages each one only once.
+    pub hits: Vec<Entity>,
+}
+
+/// Tick weapons cooldown and fire projectiles at the nearest enemy.
+pub fn weapon_system(world: &mut World, physics: &PhysicsResources, index: &SpatialIndex, dt: f32) {
+    let mut projectiles = Vec::new();
+    for (_id, (weapons, handle, stats)) in world
+        .query_mut::<(&mut Weapons, &ColliderHandleComponent, Option<&Stats>)>()
+        .with::<&Player>()
+    {
+        let Some(origin) = collider_center(physics, handle) else {
+            continue;
+        };
+        let stats = stats.copied().unwrap_or_default();
+
+        let target = index
+            .enemies
+            .nearest(origin)
+            .map(|(_enemy, position)| position);
+
+        for weapon in weapons.0.iter_mut() {
+            weapon.timer -= dt;
+            if weapon.timer > 0.0 {
+                continue;
+            }
+
+            // Keep the weapon ready until an enemy shows up.
+            let Some(target) = target else {
+                continue;
+            };
+            weapon.timer = weapon.cooldown * stats.cooldown_multiplier;
+
+            let aim = (target - origin).normalize_or_zero();
+            // Center the fan on the aim direction.
+            let first_angle = -weapon.spread * (weapon.projectile_count - 1) as f32 / 2.0;
+            for i in 0..weapon.projectile_count {
+                let direction =
+                    Vec2::from_angle(first_angle + weapon.spread * i as f32).rotate(aim);
+                projectiles.push((
+                    origin,
+                    direction * weapon.projectile_speed,
+                    Projectile {
+                        damage: weapon.damage * stats.damage_multiplier,
+                        pierce: weapon.pierce,
+                        lifetime: weapon.projectile_lifetime,
+                        hits: Vec::new(),
+                    },
+                ));
+            }
+        }
+    }
+
+    for (origin, velocity, projectile) in projectiles {
+        spawn_projectile(
+            world,
+            origin,
+            velocity,
+            projectile,
+            PhysicsLayer::PlayerProjectile,
+        );
+    }
+}
+
+/// Spawn a fireball centered on `origin`, on the `PlayerProjectile` or `EnemyProjectile` layer.
+pub fn spawn_projectile(
+    world: &mut World,
+    origin: Vec2,
+    velocity: Vec2,
+    projectile: Projectile,
+    layer: PhysicsLayer,
+) -> Entity {
+    let half_size = FIREBALL_SIZE / 2.;
+    let position = origin - vec2(half_size, half_size);
+
+    let projectile_body = RigidBodyBuilder::kinematic_velocity_based()
+        .translation([position.x, position.y].into())
+        .linvel([velocity.x, velocity.y].into())
+        .build();
+    // Sensor: the projectile reports overlaps but doesn't push enemies around.
+    let projectile_collider = sensor_collider(ColliderBuilder::ball(half_size * 0.75))
+        .translation([half_size, half_size].into())
+        .build();
+
+    world.spawn((
+        projectile,
+        Transform {
+            position,
+            ..Default::default()
+        },
+        Sprite {
+            asset_id: asset_server::assets::FIREBALL.id(),
+            scale: 1.0,
+            source: None,
+        },
+        Animator::new(None),
+        layer,
+        projectile_body,
+        projectile_collider,
+    ))
+}
+
+/// Apply projectiles damage to what they touch, and remove spent projectiles.
+/// Player projectiles hurt enemies, enemy projectiles hurt the player, walls stop both.
+pub fn projectile_system(world: &mut World, dt: f32, events: &mut Events<DamageDealt>) {
+    let mut spent = Vec::new();
+    let mut damages = Vec::new();
+
+    for (id, (projectile, collide_with, layer)) in world
+        .query::<(&mut Projectile, &CollideWith, &PhysicsLayer)>()
+        .iter()
+    {
+        projectile.lifetime -= dt;
+        if projectile.lifetime <= 0.0 {
+            spent.push(id);
+            continue;
+        }
+
+        for other in collide_with.0.iter() {
+            if projectile.hits.len() > projectile.pierce as usize {
+                break;
+            }
+            let target = match layer {
+                PhysicsLayer::EnemyProjectile => world.satisfies::<&Player>(*other),
+                _ => world.satisfies::<&Enemy>(*other),
+            };
+            if projectile.hits.contains(other) || !target.unwrap_or(false) {
+                continue;
+            }
+            projectile.hits.push(*other);
+            damages.push((id, *other, projectile.damage));
+        }
+
+        let wall = collide_with.0.iter().any(|other| {
+            world
+                .get::<&PhysicsLayer>(*other)
+                .is_ok_and(|other| *other == PhysicsLayer::Wall)
+        });
+        if wall || projectile.hits.len() > projectile.pierce as usize {
+            spent.push(id);
+        }
+    }
+
+    for (source, target, damage) in damages {
+        apply_damage(world, events, source, target, damage);
+    }
+
+    for id in spent {
+        let _ = world.insert_one(id, Despawn);
+    }
+}